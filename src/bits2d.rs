@@ -1,43 +1,101 @@
-use bit_vec::BitVec;
-
+/// Each row starts on a new word, so rows can be handed out to be written separately
+#[derive(Clone, Debug)]
 pub struct Bits2d {
     width: usize,
     height: usize,
-    bv: BitVec
+    words_per_row: usize,
+    words: Vec<u32>
+}
+
+/// One row of a `Bits2d`
+#[derive(Debug)]
+pub struct RowMut<'a> {
+    words: &'a mut [u32]
+}
+
+impl RowMut<'_> {
+    pub fn set(&mut self, x: usize, b: bool) {
+        if b {
+            self.words[x/32] |= 1 << (x%32);
+        } else {
+            self.words[x/32] &= !(1 << (x%32));
+        }
+    }
+}
+
+/// A rectangle of a `Bits2d`, which can be written to at the same time as the others
+#[derive(Debug)]
+pub struct TileMut<'a> {
+    pub left: usize,
+    pub top: usize,
+    pub rows: Vec<RowMut<'a>>
 }
 
 impl Bits2d {
     pub fn new(width: usize, height: usize) -> Bits2d {
+        let words_per_row = width.div_ceil(32);
         Bits2d {
             width,
             height,
-            bv: BitVec::from_elem(width*height, false)
+            words_per_row,
+            words: vec![0; words_per_row*height]
         }
     }
     pub fn get(&self, x: usize, y: usize) -> Option<bool> {
-        self.bv.get(y*self.width+x)
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.words[y*self.words_per_row+x/32] >> (x%32) & 1 == 1)
     }
     pub fn set(&mut self, x: usize, y: usize, b: bool) {
-        self.bv.set(y*self.width+x, b);
-    }
-    pub fn set_all_zero(&mut self) {
-        self.bv.clear();
+        debug_assert!(x < self.width && y < self.height);
+        self.row_mut(y).set(x, b);
     }
     pub fn set_all_one(&mut self) {
-        self.bv.set_all();
+        self.words.fill(!0);
+    }
+    pub fn row_mut(&mut self, y: usize) -> RowMut<'_> {
+        RowMut {
+            words: &mut self.words[y*self.words_per_row..(y+1)*self.words_per_row]
+        }
+    }
+    /// Every row, each of which can be written to at the same time as the others
+    pub fn rows_mut(&mut self) -> Vec<RowMut<'_>> {
+        if self.words_per_row == 0 {
+            return (0..self.height).map(|_| RowMut { words: &mut [] }).collect();
+        }
+        self.words
+            .chunks_mut(self.words_per_row)
+            .map(|words| RowMut { words })
+            .collect()
+    }
+    /// Splits into tiles `words_wide*32` bits wide and `height` bits tall, going across then down.
+    /// The tiles on the right and bottom edges are cut off by them
+    pub fn tiles_mut(&mut self, words_wide: usize, height: usize) -> Vec<TileMut<'_>> {
+        let across = self.words_per_row.div_ceil(words_wide);
+        let down = self.height.div_ceil(height);
+        let mut tiles: Vec<TileMut> = (0..across*down)
+            .map(|i| TileMut {
+                left: i%across*words_wide*32,
+                top: i/across*height,
+                rows: Vec::new()
+            })
+            .collect();
+        for (y, row) in self.rows_mut().into_iter().enumerate() {
+            for (x, words) in row.words.chunks_mut(words_wide).enumerate() {
+                tiles[y/height*across+x].rows.push(RowMut { words });
+            }
+        }
+        tiles
     }
     /// Bits will be pretty meaningless after this,
     /// so make sure you rewrite them all before reading them
     pub fn resize(&mut self, width: usize, height: usize, new_bit_if_growing: bool) {
-        use std::cmp::Ordering::*;
         self.width = width;
         self.height = height;
-        let product = width*height;
-        match product.cmp(&self.bv.len()) {
-            Less => self.bv.truncate(product),
-            Equal => {},
-            Greater => self.bv.grow(product-self.bv.len(), new_bit_if_growing),
-        }
+        self.words_per_row = width.div_ceil(32);
+        let fill = if new_bit_if_growing { !0 } else { 0 };
+        self.words.resize(self.words_per_row*height, fill);
     }
     pub fn width(&self) -> usize {
         self.width
@@ -46,6 +104,118 @@ impl Bits2d {
         self.height
     }
     pub fn area(&self) -> usize {
-        self.bv.len()
+        self.width*self.height
+    }
+    /// Every row, one after the other
+    #[cfg(feature = "gpu")]
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+    /// Copies the bits of `values` that are set in `mask` into the top rows,
+    /// both laid out like `words`
+    #[cfg(feature = "gpu")]
+    pub fn merge_words(&mut self, values: &[u32], mask: &[u32]) {
+        for ((word, value), mask) in self.words.iter_mut().zip(values).zip(mask) {
+            *word = (*word & !mask) | (value & mask);
+        }
+    }
+    /// Sets every bit in the top `height` rows to the nearest bit
+    /// whose coordinates are both multiples of `stride`,
+    /// except for the ones set in `keep`
+    pub fn fill_from_grid(&mut self, stride: usize, height: usize, keep: &Bits2d) {
+        let height = height.min(self.height);
+        let nearest = |n: usize, len: usize| {
+            let rounded = (n + stride / 2) / stride * stride;
+            if rounded < len { rounded } else { rounded - stride }
+        };
+        for y in 0..height {
+            let grid_y = nearest(y, height);
+            for x in 0..self.width {
+                if keep.get(x, y) == Some(true) {
+                    continue;
+                }
+                let grid_x = nearest(x, self.width);
+                let b = self.get(grid_x, grid_y).unwrap();
+                self.set(x, y, b);
+            }
+        }
+    }
+    /// How many unset bits in the top `height` rows have a set bit
+    /// directly above, below, left or right of them
+    pub fn count_edge_zeros(&self, height: usize) -> usize {
+        let mut count = 0;
+        for y in 0..height.min(self.height) {
+            for x in 0..self.width {
+                if self.get(x, y) != Some(false) {
+                    continue;
+                }
+                let set = |x: Option<usize>, y: Option<usize>| match (x, y) {
+                    (Some(x), Some(y)) if x < self.width && y < height => self.get(x, y) == Some(true),
+                    _ => false
+                };
+                if set(x.checked_sub(1), Some(y)) || set(Some(x+1), Some(y)) || set(Some(x), y.checked_sub(1)) || set(Some(x), Some(y+1)) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bits2d;
+
+    #[test]
+    fn rows_are_written_separately() {
+        let mut bits = Bits2d::new(70, 3);
+        for (y, mut row) in bits.rows_mut().into_iter().enumerate() {
+            row.set(y*30, true);
+            row.set(69, true);
+        }
+        for y in 0..3 {
+            for x in 0..70 {
+                assert_eq!(bits.get(x, y), Some(x == y*30 || x == 69), "({x}, {y})");
+            }
+        }
+        assert_eq!(bits.get(70, 0), None);
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn merging_words_only_touches_masked_bits() {
+        let mut bits = Bits2d::new(40, 3);
+        bits.set(0, 0, true);
+        bits.set(1, 0, true);
+        let mut values = Bits2d::new(40, 2);
+        let mut mask = Bits2d::new(40, 2);
+        values.set(35, 1, true);
+        mask.set(35, 1, true);
+        mask.set(1, 0, true);
+        // Not in the mask
+        values.set(2, 0, true);
+        bits.merge_words(values.words(), mask.words());
+        for y in 0..3 {
+            for x in 0..40 {
+                assert_eq!(bits.get(x, y), Some((x, y) == (0, 0) || (x, y) == (35, 1)), "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_cover_every_bit_once() {
+        let mut bits = Bits2d::new(70, 5);
+        let mut tiles = bits.tiles_mut(1, 2);
+        assert_eq!(tiles.len(), 9);
+        for tile in &mut tiles {
+            for (y, row) in tile.rows.iter_mut().enumerate() {
+                row.set(tile.top+y, true);
+            }
+        }
+        for y in 0..5 {
+            for x in 0..70 {
+                assert_eq!(bits.get(x, y), Some(x%32 == y), "({x}, {y})");
+            }
+        }
     }
 }
@@ -0,0 +1,1512 @@
+use crate::{
+    Orbit, PASS_STRIDES, ParameterMapping, ParameterPlane, PixelMapping, SCHEDULE_TILE,
+    bits2d::Bits2d, viewport::Transform,
+};
+use pollster::FutureExt as _;
+use std::{
+    borrow::Cow,
+    sync::{Mutex, OnceLock},
+};
+
+/// How much estimated work per second the CPU threads and the GPU have been getting through
+#[derive(Debug, Default)]
+pub struct Throughput {
+    cpu: Option<f64>,
+    gpu: Option<f64>,
+}
+
+impl Throughput {
+    /// Even until both have been measured
+    pub fn rates(&self) -> (f64, f64) {
+        match (self.cpu, self.gpu) {
+            (Some(cpu), Some(gpu)) => (cpu, gpu),
+            _ => (1.0, 1.0),
+        }
+    }
+
+    /// Averages in a frame's measurements, so older frames count for less and less
+    pub fn measure(
+        &mut self,
+        cpu_cost: f64,
+        cpu_time: std::time::Duration,
+        gpu_cost: f64,
+        gpu_time: std::time::Duration,
+    ) {
+        for (rate, cost, time) in [
+            (&mut self.cpu, cpu_cost, cpu_time),
+            (&mut self.gpu, gpu_cost, gpu_time),
+        ] {
+            if cost > 0.0 && !time.is_zero() {
+                let measured = cost / time.as_secs_f64();
+                *rate = Some(rate.map_or(measured, |rate| (rate + measured) / 2.0));
+            }
+        }
+    }
+}
+
+/// Which tiles go to which device
+#[derive(Debug)]
+pub struct Split {
+    /// Most expensive first
+    pub cpu_order: Vec<usize>,
+    /// 1 for the tiles the GPU computes, by tile index
+    pub gpu_tiles: Vec<u32>,
+    pub cpu_cost: f64,
+    pub gpu_cost: f64,
+}
+
+/// Goes through the tiles from the most to the least expensive,
+/// giving each to whichever device would finish all of its tiles sooner with it.
+/// A rate of 0 means the device doesn't get any.
+/// Tiles that cost nothing, with all their pixels skipped, go to neither
+pub fn split_tiles(order: &[usize], costs: &[usize], cpu_rate: f64, gpu_rate: f64) -> Split {
+    let mut split = Split {
+        cpu_order: Vec::new(),
+        gpu_tiles: vec![0; costs.len()],
+        cpu_cost: 0.0,
+        gpu_cost: 0.0,
+    };
+    let finish = |cost: f64, rate: f64| {
+        if rate > 0.0 {
+            cost / rate
+        } else {
+            f64::INFINITY
+        }
+    };
+    for &i in order.iter().filter(|&&i| costs[i] > 0) {
+        let cost = costs[i] as f64;
+        if finish(split.cpu_cost + cost, cpu_rate) <= finish(split.gpu_cost + cost, gpu_rate) {
+            split.cpu_order.push(i);
+            split.cpu_cost += cost;
+        } else {
+            split.gpu_tiles[i] = 1;
+            split.gpu_cost += cost;
+        }
+    }
+    split
+}
+
+/// Why `gpu` couldn't set up a device
+#[derive(Debug, PartialEq, Eq)]
+pub enum GpuUnavailable {
+    NoAdapter,
+    RequestDevice(String),
+}
+
+impl std::fmt::Display for GpuUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GpuUnavailable::NoAdapter => write!(f, "no adapter found"),
+            GpuUnavailable::RequestDevice(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// Whether shaders can use f64, without which it has to make do with f32s
+    pub f64: bool,
+}
+
+impl Gpu {
+    /// Whether `SHADER` gets the same iteration counts as `Orbit::iterate`, which `Hybrid` needs.
+    /// `exact` is there for that, but a driver can still round differently, like in a division,
+    /// so it's checked on a view in each plane the first time it's asked
+    pub fn f64_matches_cpu(&self) -> bool {
+        static MATCHES: OnceLock<bool> = OnceLock::new();
+        self.f64 && *MATCHES.get_or_init(f64_check)
+    }
+}
+
+/// Renders `f64_matches_cpu`'s views in f64 and compares them with the CPU
+fn f64_check() -> bool {
+    let (width, height, threshhold): (usize, usize, usize) = (64, 32, 1000);
+    let tiles = vec![1; width.div_ceil(SCHEDULE_TILE) * height.div_ceil(SCHEDULE_TILE)];
+    let skip = Bits2d::new(width, height);
+    let view = |center: (f64, f64), spacing: f64, plane| ParameterMapping {
+        pixel: PixelMapping::Linear {
+            transform: Transform([[spacing, 0.0, center.0], [0.0, spacing, center.1]]),
+            shift: (-32, -16),
+        },
+        plane,
+    };
+    [
+        view((-0.743643887037, 0.131825904205), 1e-9, ParameterPlane::C),
+        view((-1.5, 0.2), 0.01, ParameterPlane::InverseC),
+        view((1.0, 0.3), 0.02, ParameterPlane::Lambda),
+    ]
+    .iter()
+    .all(|mapping| {
+        let mut counts = Vec::new();
+        for stride in PASS_STRIDES {
+            let output = calculate_gpu(
+                width,
+                height,
+                stride,
+                mapping,
+                threshhold,
+                &tiles,
+                &skip,
+                GpuPrecision::F64,
+            );
+            counts = output.iterations;
+        }
+        counts_match_cpu(&counts, width, mapping, threshhold)
+    })
+}
+
+/// Whether `counts` of a screen `width` across are what `Orbit::iterate` gets for every pixel
+fn counts_match_cpu(
+    counts: &[u32],
+    width: usize,
+    mapping: &ParameterMapping,
+    threshhold: usize,
+) -> bool {
+    counts.iter().enumerate().all(|(i, &count)| {
+        let (x0, y0) = mapping.map(i % width, i / width);
+        let mut orbit = Orbit::default();
+        orbit.iterate(x0, y0, threshhold);
+        orbit.iteration == count as usize
+    })
+}
+
+/// The first adapter, set up the first time it's asked for
+pub fn gpu() -> Result<&'static Gpu, &'static GpuUnavailable> {
+    use std::sync::LazyLock;
+    static GPU: LazyLock<Result<Gpu, GpuUnavailable>> = LazyLock::new(|| {
+        open_gpu(&wgpu::Instance::new(
+            wgpu::InstanceDescriptor::new_without_display_handle(),
+        ))
+    });
+    GPU.as_ref()
+}
+
+fn open_gpu(instance: &wgpu::Instance) -> Result<Gpu, GpuUnavailable> {
+    let adapter = instance
+        .request_adapter(&Default::default())
+        .block_on()
+        .map_err(|_| GpuUnavailable::NoAdapter)?;
+    let required_features = adapter.features() & wgpu::Features::SHADER_F64;
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            required_features,
+            ..Default::default()
+        })
+        .block_on()
+        .map_err(|error| GpuUnavailable::RequestDevice(error.to_string()))?;
+    Ok(Gpu {
+        device,
+        queue,
+        f64: !required_features.is_empty(),
+    })
+}
+
+/// Which of the shaders `calculate_gpu` runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuPrecision {
+    /// The same as the CPU
+    F64,
+    /// Each number is the sum of two f32s, which gets about 48 bits of mantissa
+    FloatFloat,
+    F32,
+}
+
+/// Finest pixel spacing the f32 shader is used for,
+/// which leaves it about 64 steps of an f32 between neighboring pixels around ±2
+const F32_MIN_SPACING: f64 = 1.0 / 65536.0;
+
+impl GpuPrecision {
+    /// The fastest one that can tell pixels `spacing` apart,
+    /// or `F64` if the results have to match the CPU's exactly and the GPU has it
+    pub fn choose(f64_support: bool, spacing: f64, exact: bool) -> Self {
+        match (f64_support, exact) {
+            (true, true) => GpuPrecision::F64,
+            _ if spacing >= F32_MIN_SPACING => GpuPrecision::F32,
+            (true, false) => GpuPrecision::F64,
+            (false, _) => GpuPrecision::FloatFloat,
+        }
+    }
+}
+
+/// WGSL shared by `SHADER` and `SHADER_F32`, which each define `Params`
+/// along with the number types of the other bindings.
+/// `output` is laid out like the rows of a `Bits2d`, twice over:
+/// first the drawn pixels, then the pixels it computed, so they can be merged in whole words.
+/// `iterations` has how many iterations each pixel it computed in any pass of the frame took,
+/// for scheduling the next frame, and 0 for the others.
+/// `skip` is laid out like `output`'s first half
+macro_rules! shader_common {
+    () => {
+        "
+const SCHEDULE_TILE: u32 = 32u;
+
+@group(0) @binding(0) var<storage, read_write> output: array<atomic<u32>>;
+@group(0) @binding(3) var<storage, read> tiles: array<u32>;
+@group(0) @binding(4) var<uniform> params: Params;
+@group(0) @binding(5) var<storage, read_write> iterations: array<u32>;
+@group(0) @binding(6) var<storage, read> skip: array<u32>;
+
+fn on_grid(px: u32, py: u32, stride: u32) -> bool {
+    return px % stride == 0 && py % stride == 0;
+}
+
+fn in_pass(px: u32, py: u32) -> bool {
+    return on_grid(px, py, params.stride)
+        && (params.first_pass != 0u || !on_grid(px, py, params.stride * 2u));
+}
+
+// Whether invocation `i` has a pixel to compute, in this pass and in one of the GPU's tiles,
+// that isn't skipped
+fn assigned(i: u32) -> bool {
+    let px = i % params.width;
+    let py = i / params.width;
+    return i < params.width * params.height
+        && in_pass(px, py)
+        && tiles[py / SCHEDULE_TILE * params.across + px / SCHEDULE_TILE] != 0u
+        && (skip[py * params.words_per_row + px / 32u] & (1u << (px % 32u))) == 0u;
+}
+
+fn write_pixel(i: u32, iteration: u32) {
+    let px = i % params.width;
+    let py = i / params.width;
+    let word = py * params.words_per_row + px / 32u;
+    let bit = 1u << (px % 32u);
+    if iteration != params.threshhold {
+        atomicOr(&output[word], bit);
+    }
+    atomicOr(&output[params.words_per_row * params.height + word], bit);
+    iterations[i] = iteration;
+}
+"
+    };
+}
+
+/// The compute shader, which computes one pixel per invocation.
+/// Everything that changes from frame to frame comes in through `params`,
+/// so it only has to be compiled once
+const SHADER: &str = concat!(
+    shader_common!(),
+    "
+struct Params {
+    transform: mat3x2<f64>,
+    center: vec2<f64>,
+    shift: vec2<f64>,
+    width: u32,
+    height: u32,
+    stride: u32,
+    first_pass: u32,
+    threshhold: u32,
+    across: u32,
+    plane: u32,
+    log_polar: u32,
+    words_per_row: u32,
+    // Always 1, see `exact`
+    one: f64,
+}
+
+@group(0) @binding(1) var<storage, read> radii: array<f64>;
+@group(0) @binding(2) var<storage, read> directions: array<vec2<f64>>;
+
+// Keeps the driver's compiler from fusing a product with the sum it goes into as an FMA,
+// which rounds once where the CPU rounds twice.
+// Every product that gets added to something goes through it
+fn exact(x: f64) -> f64 {
+    return x * params.one;
+}
+
+fn exact2(v: vec2<f64>) -> vec2<f64> {
+    return v * params.one;
+}
+
+// The same operations in the same order as `PixelMapping::map`
+fn map_pixel(px: u32, py: u32) -> vec2<f64> {
+    if params.log_polar != 0u {
+        return params.center + exact2(radii[py] * directions[px]);
+    }
+    let lattice = vec2<f64>(f64(px), f64(py)) + params.shift;
+    let t = params.transform;
+    return exact2(t[0] * lattice.x) + exact2(t[1] * lattice.y) + t[2];
+}
+
+// Same as `ParameterPlane::to_c`
+fn to_c(p: vec2<f64>) -> vec2<f64> {
+    switch params.plane {
+        case 1u: {
+            let d = exact(p.x * p.x) + exact(p.y * p.y);
+            return vec2<f64>(p.x / d, -p.y / d);
+        }
+        case 2u: {
+            let x2 = exact(p.x * p.x);
+            let y2 = exact(p.y * p.y);
+            return vec2<f64>(p.x / 2.0 - (x2 - y2) / 4.0, p.y / 2.0 - exact(p.x * p.y) / 2.0);
+        }
+        default: {
+            return p;
+        }
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn main(
+    @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+) {
+    let i = global_invocation_id.x;
+    if !assigned(i) {
+        return;
+    }
+    let c = to_c(map_pixel(i % params.width, i / params.width));
+    let x0 = c.x;
+    let y0 = c.y;
+
+    var x: f64 = 0.0;
+    var y: f64 = 0.0;
+    var x2: f64 = 0.0;
+    var y2: f64 = 0.0;
+    var iteration = 0u;
+    while (x2 + y2 <= 4.0) && (iteration < params.threshhold) {
+        y = exact((x + x) * y) + y0;
+        x = x2 - y2 + x0;
+        x2 = x * x;
+        y2 = y * y;
+        iteration += 1u;
+    }
+    write_pixel(i, iteration);
+}
+"
+);
+
+/// `SHADER` for GPUs without f64, with an entry point doing the same in plain f32s
+/// and one doing it in float-floats: pairs of f32s whose sum is the number,
+/// the second holding what the first one couldn't.
+/// Every f64 passed in is split into such a pair, which the f32 entry point only uses the first of
+const SHADER_F32: &str = concat!(
+    shader_common!(),
+    "
+// The vectors are x, what x rounded off, y, what y rounded off.
+// The transform's columns are vectors rather than a mat3x2<f32>,
+// whose columns aren't aligned the way some drivers want in a uniform
+struct Params {
+    transform: array<vec4<f32>, 3>,
+    center: vec4<f32>,
+    shift: vec4<f32>,
+    width: u32,
+    height: u32,
+    stride: u32,
+    first_pass: u32,
+    threshhold: u32,
+    across: u32,
+    plane: u32,
+    log_polar: u32,
+    words_per_row: u32,
+    // Always 1, see `exact`
+    one: f32,
+}
+
+@group(0) @binding(1) var<storage, read> radii: array<vec2<f32>>;
+@group(0) @binding(2) var<storage, read> directions: array<vec4<f32>>;
+
+// Keeps the driver's compiler from simplifying (a + b) - a to b,
+// which is what the error-free transformations below need it not to do
+fn exact(x: f32) -> f32 {
+    return x * params.one;
+}
+
+// Error-free transformations, from Dekker and Knuth
+
+fn two_sum(a: f32, b: f32) -> vec2<f32> {
+    let s = exact(a + b);
+    let v = exact(s - a);
+    return vec2<f32>(s, (a - (s - v)) + (b - v));
+}
+
+fn quick_two_sum(a: f32, b: f32) -> vec2<f32> {
+    let s = exact(a + b);
+    return vec2<f32>(s, b - (s - a));
+}
+
+fn split(a: f32) -> vec2<f32> {
+    let t = exact(4097.0 * a);
+    let hi = exact(t - (t - a));
+    return vec2<f32>(hi, a - hi);
+}
+
+fn two_prod(a: f32, b: f32) -> vec2<f32> {
+    let p = exact(a * b);
+    let sa = split(a);
+    let sb = split(b);
+    return vec2<f32>(p, ((sa.x * sb.x - p) + sa.x * sb.y + sa.y * sb.x) + sa.y * sb.y);
+}
+
+fn ff_add(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let s = two_sum(a.x, b.x);
+    let t = two_sum(a.y, b.y);
+    let r = quick_two_sum(s.x, s.y + t.x);
+    return quick_two_sum(r.x, r.y + t.y);
+}
+
+fn ff_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let p = two_prod(a.x, b.x);
+    return quick_two_sum(p.x, p.y + (a.x * b.y + a.y * b.x));
+}
+
+fn ff_div(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let q = a.x / b.x;
+    let r = ff_add(a, -ff_mul(b, vec2<f32>(q, 0.0)));
+    return quick_two_sum(q, r.x / b.x);
+}
+
+// x and y of the point, each as a float-float
+fn map_pixel_ff(px: u32, py: u32) -> vec4<f32> {
+    if params.log_polar != 0u {
+        let r = radii[py];
+        let d = directions[px];
+        return vec4<f32>(
+            ff_add(params.center.xy, ff_mul(r, d.xy)),
+            ff_add(params.center.zw, ff_mul(r, d.zw)),
+        );
+    }
+    let lx = ff_add(vec2<f32>(f32(px), 0.0), params.shift.xy);
+    let ly = ff_add(vec2<f32>(f32(py), 0.0), params.shift.zw);
+    let a = params.transform[0];
+    let b = params.transform[1];
+    let c = params.transform[2];
+    return vec4<f32>(
+        ff_add(ff_add(ff_mul(a.xy, lx), ff_mul(b.xy, ly)), c.xy),
+        ff_add(ff_add(ff_mul(a.zw, lx), ff_mul(b.zw, ly)), c.zw),
+    );
+}
+
+fn to_c_ff(p: vec4<f32>) -> vec4<f32> {
+    let x = p.xy;
+    let y = p.zw;
+    switch params.plane {
+        case 1u: {
+            let d = ff_add(ff_mul(x, x), ff_mul(y, y));
+            return vec4<f32>(ff_div(x, d), -ff_div(y, d));
+        }
+        case 2u: {
+            let xy = ff_mul(x, y);
+            return vec4<f32>(
+                ff_add(x * 0.5, -ff_add(ff_mul(x, x), -ff_mul(y, y)) * 0.25),
+                ff_add(y * 0.5, -xy * 0.5),
+            );
+        }
+        default: {
+            return p;
+        }
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn main_float_float(
+    @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+) {
+    let i = global_invocation_id.x;
+    if !assigned(i) {
+        return;
+    }
+    let c = to_c_ff(map_pixel_ff(i % params.width, i / params.width));
+    let x0 = c.xy;
+    let y0 = c.zw;
+
+    var x = vec2<f32>(0.0);
+    var y = vec2<f32>(0.0);
+    var x2 = vec2<f32>(0.0);
+    var y2 = vec2<f32>(0.0);
+    var iteration = 0u;
+    while (x2.x + y2.x <= 4.0) && (iteration < params.threshhold) {
+        y = ff_add(ff_mul(ff_add(x, x), y), y0);
+        x = ff_add(ff_add(x2, -y2), x0);
+        x2 = ff_mul(x, x);
+        y2 = ff_mul(y, y);
+        iteration += 1u;
+    }
+    write_pixel(i, iteration);
+}
+
+fn map_pixel(px: u32, py: u32) -> vec2<f32> {
+    if params.log_polar != 0u {
+        return params.center.xz + radii[py].x * directions[px].xz;
+    }
+    let lattice = vec2<f32>(f32(px), f32(py)) + (params.shift.xz + params.shift.yw);
+    let t = params.transform;
+    return lattice.x * t[0].xz + lattice.y * t[1].xz + t[2].xz;
+}
+
+fn to_c(p: vec2<f32>) -> vec2<f32> {
+    switch params.plane {
+        case 1u: {
+            let d = p.x * p.x + p.y * p.y;
+            return vec2<f32>(p.x / d, -p.y / d);
+        }
+        case 2u: {
+            return vec2<f32>(p.x / 2.0 - (p.x * p.x - p.y * p.y) / 4.0, p.y / 2.0 - p.x * p.y / 2.0);
+        }
+        default: {
+            return p;
+        }
+    }
+}
+
+@compute
+@workgroup_size(256, 1, 1)
+fn main_f32(
+    @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+) {
+    let i = global_invocation_id.x;
+    if !assigned(i) {
+        return;
+    }
+    let c = to_c(map_pixel(i % params.width, i / params.width));
+    let x0 = c.x;
+    let y0 = c.y;
+
+    var x: f32 = 0.0;
+    var y: f32 = 0.0;
+    var x2: f32 = 0.0;
+    var y2: f32 = 0.0;
+    var iteration = 0u;
+    while (x2 + y2 <= 4.0) && (iteration < params.threshhold) {
+        y = (x + x) * y + y0;
+        x = x2 - y2 + x0;
+        x2 = x * x;
+        y2 = y * y;
+        iteration += 1u;
+    }
+    write_pixel(i, iteration);
+}
+"
+);
+
+/// The uniforms of `SHADER` and `SHADER_F32`
+#[derive(Debug, PartialEq)]
+struct GpuParams {
+    /// Columns of the transform
+    transform: [[f64; 2]; 3],
+    center: [f64; 2],
+    /// Whole numbers, but past what an `i32` holds once the view has been panned far enough
+    shift: [f64; 2],
+    width: u32,
+    height: u32,
+    stride: u32,
+    first_pass: u32,
+    threshhold: u32,
+    /// Tiles across the screen
+    across: u32,
+    /// 0 for `C`, 1 for `InverseC` and 2 for `Lambda`
+    plane: u32,
+    log_polar: u32,
+    words_per_row: u32,
+}
+
+impl GpuParams {
+    const SIZE: usize = 128;
+
+    fn new(
+        width: usize,
+        height: usize,
+        stride: usize,
+        mapping: &ParameterMapping,
+        threshhold: usize,
+    ) -> Self {
+        let (transform, center, shift, log_polar) = match &mapping.pixel {
+            PixelMapping::Linear {
+                transform: Transform([[a, b, c], [d, e, f]]),
+                shift,
+            } => (
+                [[*a, *d], [*b, *e], [*c, *f]],
+                [0.0; 2],
+                [shift.0 as f64, shift.1 as f64],
+                0,
+            ),
+            PixelMapping::LogPolar {
+                center_x, center_y, ..
+            } => ([[0.0; 2]; 3], [*center_x, *center_y], [0.0; 2], 1),
+        };
+        Self {
+            transform,
+            center,
+            shift,
+            width: width as u32,
+            height: height as u32,
+            stride: stride as u32,
+            first_pass: u32::from(stride == PASS_STRIDES[0]),
+            threshhold: threshhold as u32,
+            across: width.div_ceil(SCHEDULE_TILE) as u32,
+            plane: match mapping.plane {
+                ParameterPlane::C => 0,
+                ParameterPlane::InverseC => 1,
+                ParameterPlane::Lambda => 2,
+            },
+            log_polar,
+            words_per_row: width.div_ceil(32) as u32,
+        }
+    }
+
+    /// In the uniform buffer layout of the shader for `precision`, which pads the end out to `SIZE`
+    fn to_bytes(&self, precision: GpuPrecision) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match precision {
+            GpuPrecision::F64 => {
+                for column in self.transform {
+                    bytes.extend(column.iter().flat_map(|x| x.to_ne_bytes()));
+                }
+                for vector in [self.center, self.shift] {
+                    bytes.extend(vector.iter().flat_map(|x| x.to_ne_bytes()));
+                }
+            }
+            GpuPrecision::FloatFloat | GpuPrecision::F32 => {
+                for vector in self.transform.iter().chain([&self.center, &self.shift]) {
+                    bytes.extend(
+                        vector
+                            .iter()
+                            .flat_map(|&x| split_f64(x))
+                            .flat_map(f32::to_ne_bytes),
+                    );
+                }
+            }
+        }
+        for x in [
+            self.width,
+            self.height,
+            self.stride,
+            self.first_pass,
+            self.threshhold,
+            self.across,
+            self.plane,
+            self.log_polar,
+            self.words_per_row,
+        ] {
+            bytes.extend(x.to_ne_bytes());
+        }
+        // `one`, after the f64 one is aligned to 8 bytes
+        match precision {
+            GpuPrecision::F64 => {
+                bytes.resize(bytes.len().next_multiple_of(8), 0);
+                bytes.extend(1f64.to_ne_bytes());
+            }
+            GpuPrecision::FloatFloat | GpuPrecision::F32 => bytes.extend(1f32.to_ne_bytes()),
+        }
+        bytes.resize(Self::SIZE, 0);
+        bytes
+    }
+}
+
+/// The nearest f32 to `x`, and the nearest f32 to what that's off by
+fn split_f64(x: f64) -> [f32; 2] {
+    let hi = x as f32;
+    [hi, (x - hi as f64) as f32]
+}
+
+/// The pipelines and buffers of `calculate_gpu`, kept from one call to the next
+struct GpuState {
+    f64: Option<wgpu::ComputePipeline>,
+    float_float: wgpu::ComputePipeline,
+    f32: wgpu::ComputePipeline,
+    /// Shared by all of the pipelines, so one bind group does for all of them
+    layout: wgpu::BindGroupLayout,
+    params: wgpu::Buffer,
+    /// The most recently used first, so calibrating on part of the screen
+    /// doesn't make the whole screen's buffers over again
+    screens: Vec<GpuScreen>,
+}
+
+/// How many sizes of `GpuScreen` are kept
+const SCREENS: usize = 2;
+
+/// The buffers sized for one screen
+struct GpuScreen {
+    width: usize,
+    height: usize,
+    output: wgpu::Buffer,
+    iterations: wgpu::Buffer,
+    /// `output` followed by `iterations`, only the first of which is read back before the last pass
+    readback: wgpu::Buffer,
+    radii: wgpu::Buffer,
+    directions: wgpu::Buffer,
+    tiles: wgpu::Buffer,
+    skip: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GpuState {
+    fn new(gpu: &Gpu) -> Self {
+        let device = &gpu.device;
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage(0, false),
+                storage(1, true),
+                storage(2, true),
+                storage(3, true),
+                storage(5, false),
+                storage(6, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[Some(&layout)],
+            immediate_size: 0,
+        });
+        let pipeline = |source: &'static str, entry_point| {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(Cow::from(source)),
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: Default::default(),
+            })
+        };
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params"),
+            size: GpuParams::SIZE as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            f64: gpu.f64.then(|| pipeline(SHADER, "main")),
+            float_float: pipeline(SHADER_F32, "main_float_float"),
+            f32: pipeline(SHADER_F32, "main_f32"),
+            layout,
+            params,
+            screens: Vec::new(),
+        }
+    }
+
+    fn pipeline(&self, precision: GpuPrecision) -> &wgpu::ComputePipeline {
+        match precision {
+            GpuPrecision::F64 => self
+                .f64
+                .as_ref()
+                .expect("F64 is only chosen when the GPU has it"),
+            GpuPrecision::FloatFloat => &self.float_float,
+            GpuPrecision::F32 => &self.f32,
+        }
+    }
+
+    /// The buffers for a `width` by `height` screen
+    fn screen(&mut self, device: &wgpu::Device, width: usize, height: usize) -> &GpuScreen {
+        use wgpu::BufferUsages;
+        if let Some(i) = self
+            .screens
+            .iter()
+            .position(|screen| (screen.width, screen.height) == (width, height))
+        {
+            let screen = self.screens.remove(i);
+            self.screens.insert(0, screen);
+            return &self.screens[0];
+        }
+        let buffer = |label, size: usize, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as u64,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let output_size = width.div_ceil(32) * height * 2 * 4;
+        let output = buffer(
+            "output",
+            output_size,
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        );
+        let iterations = buffer(
+            "iterations",
+            width * height * 4,
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        );
+        let readback = buffer(
+            "readback",
+            output_size + width * height * 4,
+            BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        );
+        // An f64 or a float-float for each row, and two for each column
+        let radii = buffer(
+            "radii",
+            height * 8,
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        );
+        let directions = buffer(
+            "directions",
+            width * 16,
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        );
+        let tiles = buffer(
+            "tiles",
+            width.div_ceil(SCHEDULE_TILE) * height.div_ceil(SCHEDULE_TILE) * 4,
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        );
+        let skip = buffer(
+            "skip",
+            width.div_ceil(32) * height * 4,
+            BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.layout,
+            entries: &[
+                &output,
+                &radii,
+                &directions,
+                &tiles,
+                &self.params,
+                &iterations,
+                &skip,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        });
+        self.screens.truncate(SCREENS - 1);
+        self.screens.insert(
+            0,
+            GpuScreen {
+                width,
+                height,
+                output,
+                iterations,
+                readback,
+                radii,
+                directions,
+                tiles,
+                skip,
+                bind_group,
+            },
+        );
+        &self.screens[0]
+    }
+}
+
+/// What `calculate_gpu` read back
+pub struct GpuOutput {
+    /// Which pixels are drawn and which it computed, as laid out by `shader_common!`
+    words: Vec<u32>,
+    /// How many iterations each pixel it computed in any pass of the frame took, or 0.
+    /// Only read back with the last pass, so it's empty for the others
+    iterations: Vec<u32>,
+}
+
+/// Computes a pass of the pixels in the tiles marked 1 in `tiles`, except for the ones set in `skip`.
+/// With `GpuPrecision::F64` it does the same f64 operations as `Orbit::iterate` on the same
+/// lattice coordinates, so `Hybrid` frames match the CPU's bit for bit
+/// on the GPUs that `Gpu::f64_matches_cpu` finds do them the same way
+pub fn calculate_gpu(
+    width: usize,
+    height: usize,
+    stride: usize,
+    mapping: &ParameterMapping,
+    threshhold: usize,
+    tiles: &[u32],
+    skip: &Bits2d,
+    precision: GpuPrecision,
+) -> GpuOutput {
+    static STATE: Mutex<Option<GpuState>> = Mutex::new(None);
+    let gpu = gpu().expect("the GPU methods are skipped without one");
+    let (device, queue) = (&gpu.device, &gpu.queue);
+    if width == 0 || height == 0 {
+        return GpuOutput {
+            words: Vec::new(),
+            iterations: Vec::new(),
+        };
+    }
+    let mut state = STATE.lock().unwrap();
+    let state = state.get_or_insert_with(|| GpuState::new(gpu));
+    queue.write_buffer(
+        &state.params,
+        0,
+        &GpuParams::new(width, height, stride, mapping, threshhold).to_bytes(precision),
+    );
+    let pipeline = state.pipeline(precision).clone();
+    let screen = state.screen(device, width, height);
+    // Log polar needs exp, sin and cos, which aren't available for f64 in WGSL,
+    // so they're precomputed on the CPU and passed in as tables
+    if let PixelMapping::LogPolar {
+        radii, directions, ..
+    } = &mapping.pixel
+    {
+        let bytes = |numbers: &mut dyn Iterator<Item = f64>| -> Vec<u8> {
+            match precision {
+                GpuPrecision::F64 => numbers.flat_map(f64::to_ne_bytes).collect(),
+                GpuPrecision::FloatFloat | GpuPrecision::F32 => numbers
+                    .flat_map(split_f64)
+                    .flat_map(f32::to_ne_bytes)
+                    .collect(),
+            }
+        };
+        queue.write_buffer(&screen.radii, 0, &bytes(&mut radii.iter().copied()));
+        queue.write_buffer(
+            &screen.directions,
+            0,
+            &bytes(&mut directions.iter().flat_map(|&(cos, sin)| [cos, sin])),
+        );
+    }
+    queue.write_buffer(
+        &screen.tiles,
+        0,
+        &tiles
+            .iter()
+            .flat_map(|tile| tile.to_ne_bytes())
+            .collect::<Vec<u8>>(),
+    );
+    queue.write_buffer(
+        &screen.skip,
+        0,
+        &skip
+            .words()
+            .iter()
+            .flat_map(|word| word.to_ne_bytes())
+            .collect::<Vec<u8>>(),
+    );
+
+    let last_pass = stride == 1;
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.clear_buffer(&screen.output, 0, None);
+    if stride == PASS_STRIDES[0] {
+        encoder.clear_buffer(&screen.iterations, 0, None);
+    }
+    {
+        let num_dispatchers = (width * height).div_ceil(256) as u32;
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &screen.bind_group, &[]);
+        pass.dispatch_workgroups(num_dispatchers, 1, 1);
+    }
+    encoder.copy_buffer_to_buffer(&screen.output, 0, &screen.readback, 0, None);
+    // The counts are four bytes a pixel where the output is two bits,
+    // and they're only needed once the frame is done
+    let readback_size = if last_pass {
+        encoder.copy_buffer_to_buffer(
+            &screen.iterations,
+            0,
+            &screen.readback,
+            screen.output.size(),
+            None,
+        );
+        screen.readback.size()
+    } else {
+        screen.output.size()
+    };
+    queue.submit([encoder.finish()]);
+
+    {
+        let (tx, rx) = std::sync::mpsc::channel();
+        screen
+            .readback
+            .map_async(wgpu::MapMode::Read, ..readback_size, move |result| {
+                tx.send(result).unwrap()
+            });
+        device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+        let _ = rx.recv().unwrap();
+    }
+    let mut words: Vec<u32> = screen
+        .readback
+        .get_mapped_range(..readback_size)
+        .chunks_exact(4)
+        .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+        .collect();
+    screen.readback.unmap();
+    let iterations = if last_pass {
+        words.split_off(words.len() - width * height)
+    } else {
+        Vec::new()
+    };
+    GpuOutput { words, iterations }
+}
+
+/// Copies the pixels of `output` from `calculate_gpu` that it computed into the top rows of `bits`,
+/// and after the last pass, how many iterations the frame's took into `iterations`
+pub fn write_gpu_output(output: &GpuOutput, bits: &mut Bits2d, iterations: &mut [usize]) {
+    let (values, computed) = output.words.split_at(output.words.len() / 2);
+    bits.merge_words(values, computed);
+    for (pixel, &n) in output.iterations.iter().enumerate() {
+        if n != 0 {
+            iterations[pixel] = n as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        GpuOutput, GpuParams, GpuPrecision, GpuUnavailable, SHADER, SHADER_F32, Throughput,
+        calculate_gpu, counts_match_cpu, gpu, open_gpu, split_f64, split_tiles, write_gpu_output,
+    };
+    use crate::{
+        Orbit, PASS_STRIDES, ParameterMapping, ParameterPlane, PixelMapping, SCHEDULE_TILE,
+        bits2d::Bits2d, calculate_cpu_multithread, calculate_cpu_singlethread, in_pass, schedule,
+        viewport::Transform, worker::CancelToken,
+    };
+    use std::time::Duration;
+
+    /// A `PixelMapping::Linear` onto `plane`
+    fn linear(
+        transform: [[f64; 3]; 2],
+        shift: (i64, i64),
+        plane: ParameterPlane,
+    ) -> ParameterMapping {
+        ParameterMapping {
+            pixel: PixelMapping::Linear {
+                transform: Transform(transform),
+                shift,
+            },
+            plane,
+        }
+    }
+
+    fn pool(threads: usize) -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+    }
+
+    /// Readers for the fields of `GpuParams::to_bytes`
+    fn f64_at(bytes: &[u8], offset: usize) -> f64 {
+        f64::from_ne_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+        f32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// The f64 a FloatFloat's high and low f32s add up to
+    fn float_float(bytes: &[u8], hi: usize, lo: usize) -> f64 {
+        f32_at(bytes, hi) as f64 + f32_at(bytes, lo) as f64
+    }
+
+    #[test]
+    fn tiles_are_split_by_throughput() {
+        let order = [0, 1, 2, 3];
+        let costs = [400, 300, 200, 100];
+        // The GPU is three times as fast, so it gets about three quarters of the work
+        let split = split_tiles(&order, &costs, 1.0, 3.0);
+        assert_eq!(split.cpu_order, [2]);
+        assert_eq!(split.gpu_tiles, [1, 1, 0, 1]);
+        assert_eq!((split.cpu_cost, split.gpu_cost), (200.0, 800.0));
+        // A device with no throughput doesn't get any
+        let split = split_tiles(&order, &costs, 1.0, 0.0);
+        assert_eq!(split.cpu_order, order);
+        assert_eq!(split.gpu_tiles, [0; 4]);
+        // Tiles with every pixel already known go to neither
+        let split = split_tiles(&order, &[400, 0, 200, 0], 0.0, 1.0);
+        assert!(split.cpu_order.is_empty());
+        assert_eq!(split.gpu_tiles, [1, 0, 1, 0]);
+        let split = split_tiles(&order, &costs, 0.0, 1.0);
+        assert!(split.cpu_order.is_empty());
+        assert_eq!(split.gpu_tiles, [1; 4]);
+        let mut throughput = Throughput::default();
+        assert_eq!(throughput.rates(), (1.0, 1.0));
+        throughput.measure(100.0, Duration::from_secs(1), 900.0, Duration::from_secs(1));
+        throughput.measure(100.0, Duration::from_secs(1), 900.0, Duration::from_secs(3));
+        assert_eq!(throughput.rates(), (100.0, 600.0));
+    }
+
+    #[test]
+    fn split_frames_merge_into_the_same_bits() {
+        let mapping = linear(
+            [[0.03, 0.0, -0.7], [0.0, 0.04, 0.0]],
+            (-50, -20),
+            ParameterPlane::C,
+        );
+        let (width, height) = (100, 41);
+        let pool = pool(2);
+        // All of the first tile and a few pixels of the others
+        let mut skip = Bits2d::new(width, height);
+        for py in 0..height {
+            for px in 0..width {
+                if (px < SCHEDULE_TILE && py < SCHEDULE_TILE) || (px + py) % 7 == 0 {
+                    skip.set(px, py, true);
+                }
+            }
+        }
+        let (order, costs) = schedule(width, height, &skip, &[]);
+        let split = split_tiles(&order, &costs, 1.0, 1.0);
+        assert!(!split.cpu_order.is_empty() && split.gpu_tiles.contains(&1));
+        assert!(!split.cpu_order.contains(&0) && split.gpu_tiles[0] == 0);
+        let on_gpu = |x: usize, y: usize| {
+            let tile = y / SCHEDULE_TILE * width.div_ceil(SCHEDULE_TILE) + x / SCHEDULE_TILE;
+            split.gpu_tiles[tile] == 1 && skip.get(x, y) == Some(false)
+        };
+        let cancel = CancelToken::default();
+        let mut single = Bits2d::new(width, height);
+        let mut merged = single.clone();
+        let mut single_orbits = vec![Orbit::default(); width * height];
+        let mut merged_orbits = single_orbits.clone();
+        let mut iterations = vec![0; width * height];
+        for stride in PASS_STRIDES {
+            calculate_cpu_singlethread(
+                width,
+                height,
+                stride,
+                &mapping,
+                100,
+                &skip,
+                &mut single_orbits,
+                &cancel,
+                &mut single,
+            );
+            calculate_cpu_multithread(
+                width,
+                height,
+                stride,
+                &mapping,
+                100,
+                &skip,
+                &mut merged_orbits,
+                &split.cpu_order,
+                &pool,
+                &cancel,
+                &mut merged,
+            );
+            // What the GPU should write, from orbits of its own
+            let mut values = Bits2d::new(width, height);
+            let mut computed = Bits2d::new(width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    if in_pass(x, y, stride) && on_gpu(x, y) {
+                        let (x0, y0) = mapping.map(x, y);
+                        let mut orbit = Orbit::default();
+                        orbit.iterate(x0, y0, 100);
+                        values.set(x, y, orbit.drawn(100).unwrap());
+                        computed.set(x, y, true);
+                    }
+                }
+            }
+            // Counts for the whole frame come with the last pass
+            let counts = (0..width * height)
+                .map(|i| u32::from(on_gpu(i % width, i / width)) * 7)
+                .collect();
+            let output = GpuOutput {
+                words: [values.words(), computed.words()].concat(),
+                iterations: if stride == 1 { counts } else { Vec::new() },
+            };
+            write_gpu_output(&output, &mut merged, &mut iterations);
+        }
+        for py in 0..height {
+            for px in 0..width {
+                assert_eq!(single.get(px, py), merged.get(px, py), "({px}, {py})");
+                let expected = if on_gpu(px, py) { 7 } else { 0 };
+                assert_eq!(iterations[py * width + px], expected, "({px}, {py})");
+            }
+        }
+    }
+
+    #[test]
+    fn no_adapter_leaves_the_gpu_unavailable() {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::empty(),
+            ..wgpu::InstanceDescriptor::new_without_display_handle()
+        });
+        let unavailable = open_gpu(&instance).err().unwrap();
+        assert_eq!(unavailable, GpuUnavailable::NoAdapter);
+        assert_eq!(unavailable.to_string(), "no adapter found");
+    }
+
+    fn validate(source: &str, capabilities: naga::valid::Capabilities) -> naga::Module {
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), capabilities)
+            .validate(&module)
+            .unwrap();
+        module
+    }
+
+    /// Member offsets and size of the shader's `Params`
+    fn params_layout(module: &naga::Module) -> (Vec<u32>, usize) {
+        module
+            .types
+            .iter()
+            .find_map(|(_, ty)| match &ty.inner {
+                naga::TypeInner::Struct { members, span }
+                    if ty.name.as_deref() == Some("Params") =>
+                {
+                    Some((
+                        members.iter().map(|member| member.offset).collect(),
+                        *span as usize,
+                    ))
+                }
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn shader_validates_with_f64() {
+        let module = validate(SHADER, naga::valid::Capabilities::FLOAT64);
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::FLOAT64,
+        )
+        .validate(&module)
+        .unwrap();
+        // And needs it
+        assert!(
+            naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                naga::valid::Capabilities::empty(),
+            )
+            .validate(&module)
+            .is_err()
+        );
+        assert!(SHADER.contains(&format!("const SCHEDULE_TILE: u32 = {SCHEDULE_TILE}u;")));
+    }
+
+    #[test]
+    fn f32_shader_validates_without_f64() {
+        let module = validate(SHADER_F32, naga::valid::Capabilities::empty());
+        let (offsets, span) = params_layout(&module);
+        assert_eq!(
+            offsets,
+            [0, 48, 64, 80, 84, 88, 92, 96, 100, 104, 108, 112, 116]
+        );
+        assert_eq!(span, GpuParams::SIZE);
+        let params = GpuParams::new(
+            10,
+            10,
+            1,
+            &linear(
+                [[0.1, 0.0, -0.75], [0.0, 0.1, 1.0 / 3.0]],
+                (0, 0),
+                ParameterPlane::C,
+            ),
+            100,
+        );
+        let bytes = params.to_bytes(GpuPrecision::FloatFloat);
+        assert_eq!(bytes.len(), GpuParams::SIZE);
+        // The rounded off part makes up most of the difference
+        assert_eq!(f32_at(&bytes, 0), 0.1);
+        assert_ne!(f32_at(&bytes, 4), 0.0);
+        assert!((float_float(&bytes, 0, 4) - 0.1).abs() < 1e-15);
+        assert_eq!(float_float(&bytes, 32, 36), -0.75);
+        assert!((float_float(&bytes, 40, 44) - 1.0 / 3.0).abs() < 1e-15);
+        assert_eq!(f32_at(&bytes, 116), 1.0);
+        assert_eq!(params.to_bytes(GpuPrecision::F32), bytes);
+    }
+
+    #[test]
+    fn precision_follows_pixel_spacing_and_f64_support() {
+        let shallow = 0.01;
+        let deep = 1e-9;
+        assert_eq!(
+            GpuPrecision::choose(true, shallow, false),
+            GpuPrecision::F32
+        );
+        assert_eq!(
+            GpuPrecision::choose(false, shallow, false),
+            GpuPrecision::F32
+        );
+        assert_eq!(GpuPrecision::choose(true, deep, false), GpuPrecision::F64);
+        assert_eq!(
+            GpuPrecision::choose(false, deep, false),
+            GpuPrecision::FloatFloat
+        );
+        assert_eq!(GpuPrecision::choose(true, shallow, true), GpuPrecision::F64);
+        assert_eq!(split_f64(0.5), [0.5, 0.0]);
+        let [hi, lo] = split_f64(std::f64::consts::PI);
+        assert_eq!(hi, std::f32::consts::PI);
+        assert!((hi as f64 + lo as f64 - std::f64::consts::PI).abs() < 1e-14);
+    }
+
+    /// Renders on the GPU and on the CPU, returning how many pixels they disagree on
+    fn gpu_mismatches(
+        mapping: &ParameterMapping,
+        threshhold: usize,
+        precision: GpuPrecision,
+    ) -> usize {
+        let (width, height) = (96, 40);
+        let mut skip = Bits2d::new(width, height);
+        for i in (0..width * height).step_by(7) {
+            skip.set(i % width, i / width, true);
+        }
+        let tiles = vec![1; width.div_ceil(SCHEDULE_TILE) * height.div_ceil(SCHEDULE_TILE)];
+        let mut gpu_bits = Bits2d::new(width, height);
+        let mut cpu_bits = Bits2d::new(width, height);
+        let mut orbits = vec![Orbit::default(); width * height];
+        let mut iterations = vec![0; width * height];
+        for stride in PASS_STRIDES {
+            let output = calculate_gpu(
+                width, height, stride, mapping, threshhold, &tiles, &skip, precision,
+            );
+            write_gpu_output(&output, &mut gpu_bits, &mut iterations);
+            calculate_cpu_singlethread(
+                width,
+                height,
+                stride,
+                mapping,
+                threshhold,
+                &skip,
+                &mut orbits,
+                &CancelToken::default(),
+                &mut cpu_bits,
+            );
+        }
+        let mismatches = (0..width * height)
+            .filter(|i| gpu_bits.get(i % width, i / width) != cpu_bits.get(i % width, i / width))
+            .count();
+        // Both sides of the boundary should be on screen
+        assert!((0..width * height).any(|i| cpu_bits.get(i % width, i / width) == Some(true)));
+        assert!((0..width * height).any(|i| cpu_bits.get(i % width, i / width) == Some(false)));
+        // Every pixel but the skipped ones was computed, in at least one iteration
+        for (i, &n) in iterations.iter().enumerate() {
+            if skip.get(i % width, i / width) == Some(true) {
+                assert_eq!(n, 0);
+            } else {
+                assert!((1..=threshhold).contains(&n));
+            }
+        }
+        if precision == GpuPrecision::F64 {
+            let cpu_iterations: Vec<usize> = orbits.iter().map(|orbit| orbit.iteration).collect();
+            assert_eq!(iterations, cpu_iterations);
+        }
+        mismatches
+    }
+
+    #[test]
+    fn gpu_precisions_agree_with_the_cpu() {
+        // There's nothing to compare without an adapter
+        let Ok(gpu) = gpu() else {
+            return;
+        };
+        let view = |center: (f64, f64), spacing: f64, plane| {
+            linear(
+                [[spacing, 0.0, center.0], [0.0, spacing, center.1]],
+                (-48, -20),
+                plane,
+            )
+        };
+        let shallow = view((-0.75, 0.1), 0.03, ParameterPlane::C);
+        let deep = view((-0.743643887037, 0.131825904205), 1e-9, ParameterPlane::C);
+        let inverse = view((-1.5, 0.2), 0.01, ParameterPlane::InverseC);
+        // Chaotic orbits next to the boundary can go either way, but only a few
+        let few = 96 * 40 / 50;
+        assert!(gpu_mismatches(&shallow, 200, GpuPrecision::F32) <= few);
+        assert!(gpu_mismatches(&shallow, 200, GpuPrecision::FloatFloat) <= few);
+        assert!(gpu_mismatches(&deep, 2000, GpuPrecision::FloatFloat) <= few);
+        assert!(gpu_mismatches(&inverse, 200, GpuPrecision::FloatFloat) <= few);
+        if gpu.f64 {
+            assert_eq!(gpu_mismatches(&deep, 2000, GpuPrecision::F64), 0);
+            assert!(gpu.f64_matches_cpu());
+        }
+    }
+
+    #[test]
+    fn f64_check_compares_every_count() {
+        let mapping = linear(
+            [[0.05, 0.0, -0.75], [0.0, 0.05, 0.0]],
+            (-8, -4),
+            ParameterPlane::Lambda,
+        );
+        let (width, height) = (16, 8);
+        let mut counts: Vec<u32> = (0..width * height)
+            .map(|i| {
+                let (x0, y0) = mapping.map(i % width, i / width);
+                let mut orbit = Orbit::default();
+                orbit.iterate(x0, y0, 100);
+                orbit.iteration as u32
+            })
+            .collect();
+        assert!(counts_match_cpu(&counts, width, &mapping, 100));
+        // Off by one iteration in one pixel, like a fused multiply-add could make it
+        counts[width * height - 1] += 1;
+        assert!(!counts_match_cpu(&counts, width, &mapping, 100));
+    }
+
+    #[test]
+    fn params_are_laid_out_like_the_shader_expects() {
+        let module = validate(SHADER, naga::valid::Capabilities::FLOAT64);
+        let (offsets, span) = params_layout(&module);
+        assert_eq!(
+            offsets,
+            [0, 48, 64, 80, 84, 88, 92, 96, 100, 104, 108, 112, 120]
+        );
+        assert_eq!(span, GpuParams::SIZE);
+        let params = GpuParams::new(
+            100,
+            40,
+            PASS_STRIDES[0],
+            &linear(
+                [[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]],
+                (-50, 7),
+                ParameterPlane::Lambda,
+            ),
+            500,
+        );
+        let bytes = params.to_bytes(GpuPrecision::F64);
+        assert_eq!(bytes.len(), GpuParams::SIZE);
+        // Columns of the transform
+        assert_eq!(
+            [f64_at(&bytes, 0), f64_at(&bytes, 8), f64_at(&bytes, 16)],
+            [1.0, 4.0, 2.0]
+        );
+        assert_eq!(f64_at(&bytes, 40), 6.0);
+        assert_eq!([f64_at(&bytes, 64), f64_at(&bytes, 72)], [-50.0, 7.0]);
+        assert_eq!(u32_at(&bytes, 80), 100);
+        assert_eq!(u32_at(&bytes, 92), 1);
+        assert_eq!(u32_at(&bytes, 96), 500);
+        assert_eq!(u32_at(&bytes, 100), 4);
+        assert_eq!(u32_at(&bytes, 104), 2);
+        assert_eq!(u32_at(&bytes, 108), 0);
+        assert_eq!(u32_at(&bytes, 112), 4);
+        assert_eq!(f64_at(&bytes, 120), 1.0);
+    }
+
+    #[test]
+    fn shifts_past_i32_reach_the_shader_exactly() {
+        let far = (1i64 << 40) + 3;
+        let params = GpuParams::new(
+            10,
+            10,
+            1,
+            &linear(
+                [[1e-12, 0.0, 0.0], [0.0, 1e-12, 0.0]],
+                (far, -far),
+                ParameterPlane::C,
+            ),
+            100,
+        );
+        let bytes = params.to_bytes(GpuPrecision::F64);
+        assert_eq!(
+            [f64_at(&bytes, 64), f64_at(&bytes, 72)],
+            [far as f64, -far as f64]
+        );
+        let bytes = params.to_bytes(GpuPrecision::FloatFloat);
+        assert_eq!(float_float(&bytes, 64, 68), far as f64);
+        assert_eq!(float_float(&bytes, 72, 76), -far as f64);
+    }
+}
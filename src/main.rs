@@ -1,523 +1,2225 @@
 #![allow(clippy::too_many_arguments)]
 
 mod bits2d;
+#[cfg(feature = "gpu")]
+mod gpu;
 mod sextant_terminal;
+mod tile_cache;
+mod viewport;
+mod worker;
 
-use dashmap::DashMap;
-use pollster::FutureExt as _;
+use bits2d::{Bits2d, RowMut};
+#[cfg(feature = "gpu")]
+use gpu::{
+    Gpu, GpuOutput, GpuPrecision, GpuUnavailable, Throughput, calculate_gpu, gpu, split_tiles,
+    write_gpu_output,
+};
 use rayon::prelude::*;
 use std::{
-    borrow::Cow,
-    sync::{
-        Arc, Mutex,
-        atomic::{self, AtomicU64},
-    },
+    f64::consts::PI,
+    sync::{Arc, Mutex},
 };
+use tile_cache::TileCache;
+use viewport::{Transform, Viewport};
+use worker::{CancelToken, Worker};
 
 fn main() {
     env_logger::init();
-    let result = sextant_terminal::run(std::io::stdout(), None, on_event);
+    let pixel_aspect = arg("--pixel-aspect", "a positive number", pixel_aspect_is_valid);
+    let threads = arg("--threads", "a number", |_: &usize| true);
+    let result = sextant_terminal::run(
+        std::io::stdout(),
+        None,
+        |handler, event| on_event(handler, event, pixel_aspect, threads),
+        |handler| on_tick(handler, pixel_aspect),
+    );
     result.unwrap();
 }
 
-#[derive(Debug)]
+/// `--pixel-aspect <height/width>` overrides the shape of a sextant reported by the terminal,
+/// and `--threads <count>` sets how many threads `CpuMultiThread` uses (default: one per core)
+const USAGE: &str = "usage: mandelbrot_sextant [--pixel-aspect <height/width>] [--threads <count>]";
+
+/// The value after `name` on the command line, if it's there.
+/// Exits with the usage if the value is missing or isn't `valid`, which is described by `what`
+fn arg<T: std::str::FromStr>(name: &str, what: &str, valid: impl Fn(&T) -> bool) -> Option<T> {
+    parse_arg(std::env::args(), name, what, valid).unwrap_or_else(|error| {
+        eprintln!("{error}\n{USAGE}");
+        std::process::exit(2);
+    })
+}
+
+fn parse_arg<T: std::str::FromStr>(
+    args: impl Iterator<Item = String>,
+    name: &str,
+    what: &str,
+    valid: impl Fn(&T) -> bool,
+) -> Result<Option<T>, String> {
+    let mut args = args.skip_while(|arg| arg != name);
+    if args.next().is_none() {
+        return Ok(None);
+    }
+    let value = args.next().ok_or_else(|| format!("{name} needs a value"))?;
+    match value.parse() {
+        Ok(parsed) if valid(&parsed) => Ok(Some(parsed)),
+        _ => Err(format!("{name} should be {what}, not {value:?}")),
+    }
+}
+
+/// Zero, negative, infinite and NaN aspects would make every view degenerate
+fn pixel_aspect_is_valid(aspect: &f64) -> bool {
+    aspect.is_finite() && *aspect > 0.0
+}
+
+/// Sextants in a terminal whose cells are twice as tall as they are wide,
+/// for when the terminal doesn't report its size in pixels
+const DEFAULT_PIXEL_ASPECT: f64 = 4.0 / 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum CalculationMethod {
     CpuSingleThread,
     CpuMultiThread,
+    #[cfg(feature = "gpu")]
     Gpu,
+    /// Tiles split between the CPU threads and the GPU by how fast each has been going
+    #[cfg(feature = "gpu")]
+    Hybrid,
+    /// Whichever of the others rendered a small part of the view the fastest,
+    /// picked again when the cap or the depth moves far enough
+    Auto,
 }
 
 impl CalculationMethod {
-    fn cycle(&mut self) {
+    /// Skips the methods that need the GPU when there isn't one to use,
+    /// and `Hybrid` when it can't do f64 to match the CPU
+    fn cycle(
+        &mut self,
+        #[cfg(feature = "gpu")] gpu_available: bool,
+        #[cfg(feature = "gpu")] gpu_f64: bool,
+    ) {
         use CalculationMethod::*;
         *self = match self {
             CpuSingleThread => CpuMultiThread,
-            CpuMultiThread => Gpu,
-            Gpu => CpuSingleThread,
+            #[cfg(feature = "gpu")]
+            CpuMultiThread if gpu_available => Gpu,
+            CpuMultiThread => Auto,
+            #[cfg(feature = "gpu")]
+            Gpu if gpu_f64 => Hybrid,
+            #[cfg(feature = "gpu")]
+            Gpu | Hybrid => Auto,
+            Auto => CpuSingleThread,
         }
     }
 }
 
+#[derive(Debug)]
+enum PlotMode {
+    Mandelbrot,
+    /// Mandelbrot set on the top half, bifurcation diagram on the bottom half,
+    /// both sharing the same x axis
+    Split,
+    Bifurcation,
+}
+
+impl PlotMode {
+    fn cycle(&mut self) {
+        use PlotMode::*;
+        *self = match self {
+            Mandelbrot => Split,
+            Split => Bifurcation,
+            Bifurcation => Mandelbrot,
+        }
+    }
+
+    /// How many rows at the top of the screen show the Mandelbrot set
+    fn mandelbrot_height(&self, bit_height: usize) -> usize {
+        match self {
+            PlotMode::Mandelbrot => bit_height,
+            PlotMode::Split => bit_height / 2,
+            PlotMode::Bifurcation => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Projection {
+    Linear,
+    /// Horizontal axis is the angle around the view center,
+    /// vertical axis is the log of the distance from it
+    LogPolar,
+}
+
+impl Projection {
+    fn cycle(&mut self) {
+        use Projection::*;
+        *self = match self {
+            Linear => LogPolar,
+            LogPolar => Linear,
+        }
+    }
+}
+
+/// Which plane pan and zoom happen in,
+/// each point of which is transformed into the c of z²+c
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ParameterPlane {
+    C,
+    /// 1/c
+    InverseC,
+    /// λ of λz(1−z), which is conjugate to z²+c with c = λ/2 − λ²/4
+    Lambda,
+}
+
+impl ParameterPlane {
+    fn cycle(&mut self) {
+        use ParameterPlane::*;
+        *self = match self {
+            C => InverseC,
+            InverseC => Lambda,
+            Lambda => C,
+        }
+    }
+
+    /// (x_min, x_max, y_min, y_max) of a view showing the whole set in this plane
+    fn home(&self) -> (f64, f64, f64, f64) {
+        match self {
+            ParameterPlane::C => (-2.0, 0.47, -1.12, 1.12),
+            ParameterPlane::InverseC => (-0.6, 4.1, -2.35, 2.35),
+            ParameterPlane::Lambda => (-2.1, 4.1, -3.1, 3.1),
+        }
+    }
+
+    fn to_c(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            ParameterPlane::C => (x, y),
+            ParameterPlane::InverseC => {
+                let d = x * x + y * y;
+                (x / d, -y / d)
+            }
+            ParameterPlane::Lambda => (x / 2.0 - (x * x - y * y) / 4.0, y / 2.0 - x * y / 2.0),
+        }
+    }
+}
+
+const ZOOM_FACTORS: [f64; 3] = [2.0, 1.25, 4.0];
+
+/// About 4 MiB of tiles
+const MAX_TILES: usize = 256;
+
 #[derive(Debug)]
 struct Memory {
-    scaler_x: Scaler,
-    scaler_y: Scaler,
+    viewport: Viewport,
     threshhold: usize,
-    cache: DashMap<(HashableF64, HashableF64, usize), bool>,
+    /// Pick `threshhold` from the zoom depth and the previous frame
+    auto_threshhold: bool,
+    /// Grows while the previous frames had lots of pixels hitting the cap next to escaping ones
+    auto_boost: f64,
+    /// How many times bigger things get when zooming in
+    zoom_factor: f64,
+    cache: Arc<Mutex<TileCache<LatticeKey>>>,
+    orbits: Arc<Mutex<ViewOrbits>>,
+    /// Threads for `CpuMultiThread`
+    pool: Arc<rayon::ThreadPool>,
+    #[cfg(feature = "gpu")]
+    throughput: Arc<Mutex<Throughput>>,
+    calibration: Arc<Mutex<Option<Calibration>>>,
     calculation_method: CalculationMethod,
+    /// Or why `Gpu` and `Hybrid` are skipped
+    #[cfg(feature = "gpu")]
+    gpu: Result<&'static Gpu, &'static GpuUnavailable>,
+    plot_mode: PlotMode,
+    projection: Projection,
+    plane: ParameterPlane,
+    worker: Worker<Job, Frame>,
+    /// Set by events, and cleared by the next tick when it starts rendering
+    pending_render: bool,
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-struct HashableF64(f64);
-impl Eq for HashableF64 {}
-impl std::hash::Hash for HashableF64 {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.to_bits().hash(state);
-    }
+/// Everything needed to render a frame on the worker thread
+#[derive(Debug)]
+struct Job {
+    bit_width: usize,
+    bit_height: usize,
+    mandelbrot_height: usize,
+    viewport: Viewport,
+    mapping: ParameterMapping,
+    threshhold: usize,
+    cache: Arc<Mutex<TileCache<LatticeKey>>>,
+    orbits: Arc<Mutex<ViewOrbits>>,
+    pool: Arc<rayon::ThreadPool>,
+    #[cfg(feature = "gpu")]
+    throughput: Arc<Mutex<Throughput>>,
+    calibration: Arc<Mutex<Option<Calibration>>>,
+    calculation_method: CalculationMethod,
+    /// Goes in the title once the frame is finished
+    settings: String,
 }
-impl From<f64> for HashableF64 {
-    fn from(value: f64) -> Self {
-        Self(value)
+
+/// Sent back from the worker after each pass
+#[derive(Debug)]
+struct Frame {
+    bits: Bits2d,
+    title: String,
+    finished: bool,
+}
+
+/// Everything a pixel's orbit depends on besides its level and lattice coordinates.
+/// The cap isn't part of it, since the cache keeps how many iterations each pixel took
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct LatticeKey {
+    /// Bits of the lattice's level 0 transform
+    transform: [[u64; 3]; 2],
+    plane: ParameterPlane,
+}
+
+impl LatticeKey {
+    fn new(lattice: Transform, plane: ParameterPlane) -> Self {
+        Self {
+            transform: lattice.0.map(|row| row.map(f64::to_bits)),
+            plane,
+        }
     }
 }
 
 fn on_event(
     handler: &mut sextant_terminal::Handler<Option<Memory>>,
-    event: Option<crossterm::event::KeyEvent>,
+    event: Option<crossterm::event::Event>,
+    pixel_aspect: Option<f64>,
+    threads: Option<usize>,
 ) -> bool {
-    let mut is_pan = false;
-    if let Some(event) = event {
-        use crossterm::event::KeyCode;
-        let (additional_scaler, zoom_times, int_amount) = if event
-            .modifiers
-            .contains(crossterm::event::KeyModifiers::ALT)
-        {
-            (100, 10, 50)
-        } else {
-            (1, 1, 1)
-        };
-        match (event.code, handler.memory.as_mut()) {
-            (KeyCode::Esc | KeyCode::Char('q'), _) => return true,
-            (KeyCode::Char('w'), Some(memory)) => {
-                let amount = -memory.scaler_y.scalar * additional_scaler as f64;
-                memory.scaler_y.offset(amount);
-                is_pan = true;
-            }
-            (KeyCode::Char('s'), Some(memory)) => {
-                let amount = memory.scaler_y.scalar * additional_scaler as f64;
-                memory.scaler_y.offset(amount);
-                is_pan = true;
-            }
-            (KeyCode::Char('a'), Some(memory)) => {
-                let amount = -memory.scaler_x.scalar * additional_scaler as f64;
-                memory.scaler_x.offset(amount);
-                is_pan = true;
-            }
-            (KeyCode::Char('d'), Some(memory)) => {
-                let amount = memory.scaler_x.scalar * additional_scaler as f64;
-                memory.scaler_x.offset(amount);
-                is_pan = true;
-            }
-            (KeyCode::Char('='), Some(memory)) => {
-                for _ in 0..zoom_times {
-                    memory.scaler_x.zoom_in();
-                    memory.scaler_y.zoom_in();
+    let bit_width = handler.bit_width();
+    let bit_height = handler.bit_height();
+    let aspect = pixel_aspect
+        .or_else(|| handler.bit_aspect())
+        .unwrap_or(DEFAULT_PIXEL_ASPECT);
+    match event {
+        None => {}
+        // The viewport keeps its center pixel and scale when the screen changes size,
+        // so everything that's still on screen samples the same points as before
+        Some(crossterm::event::Event::Resize(..)) => {}
+        Some(crossterm::event::Event::Mouse(event)) => {
+            use crossterm::event::MouseEventKind;
+            let Some(memory) = handler.memory.as_mut() else {
+                return false;
+            };
+            let zoom = match event.kind {
+                MouseEventKind::ScrollUp => 1.0 / memory.zoom_factor,
+                MouseEventKind::ScrollDown => memory.zoom_factor,
+                _ => return false,
+            };
+            // Zoom about the pointer
+            let (px, py) = sextant_terminal::cell_to_bit(event.column, event.row);
+            let mandelbrot_height = memory.plot_mode.mandelbrot_height(bit_height);
+            memory.viewport.zoom_about(
+                zoom,
+                px as f64 - (bit_width / 2) as f64,
+                py as f64 - (mandelbrot_height / 2) as f64,
+            );
+        }
+        Some(crossterm::event::Event::Key(event)) => {
+            use crossterm::event::KeyCode;
+            // Pans move by a fraction of the screen, rounded to whole pixels
+            let (pan_fraction, zoom_times, int_amount, rotate_degrees) = if event
+                .modifiers
+                .contains(crossterm::event::KeyModifiers::ALT)
+            {
+                (2, 4, 50, 45)
+            } else {
+                (16, 1, 1, 5)
+            };
+            let pan_x = (bit_width / pan_fraction).max(1) as i64;
+            let pan_y = (bit_height / pan_fraction).max(1) as i64;
+            match (event.code, handler.memory.as_mut()) {
+                (KeyCode::Esc | KeyCode::Char('q'), _) => return true,
+                (KeyCode::Char('w'), Some(memory)) => memory.viewport.pan(0, -pan_y),
+                (KeyCode::Char('s'), Some(memory)) => memory.viewport.pan(0, pan_y),
+                (KeyCode::Char('a'), Some(memory)) => memory.viewport.pan(-pan_x, 0),
+                (KeyCode::Char('d'), Some(memory)) => memory.viewport.pan(pan_x, 0),
+                (KeyCode::Char('='), Some(memory)) => {
+                    for _ in 0..zoom_times {
+                        memory.viewport.zoom(1.0 / memory.zoom_factor);
+                    }
                 }
-            }
-            (KeyCode::Char('-'), Some(memory)) => {
-                for _ in 0..zoom_times {
-                    memory.scaler_x.zoom_out();
-                    memory.scaler_y.zoom_out();
+                (KeyCode::Char('-'), Some(memory)) => {
+                    for _ in 0..zoom_times {
+                        memory.viewport.zoom(memory.zoom_factor);
+                    }
+                }
+                (KeyCode::Char('z'), Some(memory)) => {
+                    let i = ZOOM_FACTORS
+                        .iter()
+                        .position(|&f| f == memory.zoom_factor)
+                        .unwrap_or(0);
+                    memory.zoom_factor = ZOOM_FACTORS[(i + 1) % ZOOM_FACTORS.len()];
+                }
+                (KeyCode::Char('['), Some(memory)) => {
+                    memory.viewport.rotate(rotate_degrees as f64 * PI / 180.0);
+                }
+                (KeyCode::Char(']'), Some(memory)) => {
+                    memory.viewport.rotate(-rotate_degrees as f64 * PI / 180.0);
+                }
+                // Adjusting by hand takes over from the automatic value
+                (KeyCode::Up, Some(memory)) => {
+                    memory.auto_threshhold = false;
+                    memory.threshhold += int_amount;
+                }
+                (KeyCode::Down, Some(memory)) => {
+                    memory.auto_threshhold = false;
+                    memory.threshhold = memory.threshhold.saturating_sub(int_amount);
+                }
+                (KeyCode::Char('t'), Some(memory)) => {
+                    memory.auto_threshhold = !memory.auto_threshhold;
+                    memory.auto_boost = 1.0;
+                }
+                (KeyCode::Char('m'), Some(memory)) => {
+                    memory.calculation_method.cycle(
+                        #[cfg(feature = "gpu")]
+                        memory.gpu.is_ok(),
+                        #[cfg(feature = "gpu")]
+                        memory.gpu.is_ok_and(|gpu| gpu.f64_matches_cpu()),
+                    );
+                }
+                (KeyCode::Char('b'), Some(memory)) => {
+                    memory.plot_mode.cycle();
+                }
+                (KeyCode::Char('p'), Some(memory)) => {
+                    memory.projection.cycle();
+                }
+                (KeyCode::Char('c'), Some(memory)) => {
+                    memory.plane.cycle();
+                    memory.viewport =
+                        Viewport::fit(memory.plane.home(), bit_width, bit_height, aspect);
                 }
-            }
-            (KeyCode::Up, Some(memory)) => {
-                memory.threshhold += int_amount;
-            }
-            (KeyCode::Down, Some(memory)) => {
-                memory.threshhold = memory.threshhold.saturating_sub(int_amount);
-            }
-            (KeyCode::Char('m'), Some(memory)) => {
-                memory.calculation_method.cycle();
-            }
 
-            _ => return false,
+                _ => return false,
+            }
         }
+        Some(_) => return false,
     }
-    handler.set_bits_all_zero();
-    handler.set_title("Calculating...").unwrap();
-    let start = std::time::Instant::now();
-    let memory = handler.memory.take().unwrap_or_else(|| {
-        let len = handler.bit_height().min(handler.bit_width());
-        let scaler_x = Scaler::new(0.0, len as f64, -2.0, 0.47);
-        let scaler_y = Scaler::new(0.0, len as f64, -1.12, 1.12);
+    let memory = handler.memory.get_or_insert_with(|| {
+        let plane = ParameterPlane::C;
         Memory {
-            scaler_x,
-            scaler_y,
+            viewport: Viewport::fit(plane.home(), bit_width, bit_height, aspect),
             threshhold: 500,
-            cache: DashMap::new(),
-            calculation_method: CalculationMethod::CpuSingleThread,
+            auto_threshhold: false,
+            auto_boost: 1.0,
+            zoom_factor: ZOOM_FACTORS[0],
+            cache: Arc::new(Mutex::new(TileCache::new(MAX_TILES))),
+            orbits: Arc::new(Mutex::new(ViewOrbits::default())),
+            pool: Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads.unwrap_or(0))
+                    .build()
+                    .unwrap(),
+            ),
+            #[cfg(feature = "gpu")]
+            throughput: Arc::new(Mutex::new(Throughput::default())),
+            calibration: Arc::new(Mutex::new(None)),
+            calculation_method: CalculationMethod::Auto,
+            #[cfg(feature = "gpu")]
+            gpu: gpu(),
+            plot_mode: PlotMode::Mandelbrot,
+            projection: Projection::Linear,
+            plane,
+            worker: Worker::spawn(render),
+            pending_render: false,
         }
     });
+    memory.pending_render = true;
+    false
+}
+
+/// Starts rendering the current view on the worker
+fn start_render(
+    handler: &mut sextant_terminal::Handler<Option<Memory>>,
+    pixel_aspect: Option<f64>,
+) {
+    let bit_width = handler.bit_width();
+    let bit_height = handler.bit_height();
+    let aspect = pixel_aspect
+        .or_else(|| handler.bit_aspect())
+        .unwrap_or(DEFAULT_PIXEL_ASPECT);
+    handler.set_title("Calculating...").unwrap();
+    let mut memory = handler.memory.take().unwrap();
+    memory.viewport.set_aspect(aspect);
+    if memory.auto_threshhold {
+        let home_scale = Viewport::fit(memory.plane.home(), bit_width, bit_height, aspect).scale();
+        memory.threshhold = auto_threshhold(home_scale, memory.viewport.scale(), memory.auto_boost);
+    }
     let Memory {
-        scaler_x,
-        scaler_y,
+        viewport,
         threshhold,
+        auto_threshhold,
+        auto_boost: _,
+        zoom_factor,
         cache,
+        orbits,
+        pool,
+        #[cfg(feature = "gpu")]
+        throughput,
+        calibration,
         calculation_method,
+        #[cfg(feature = "gpu")]
+        gpu,
+        plot_mode,
+        projection,
+        plane,
+        worker: _,
+        pending_render: _,
     } = &memory;
-    let bit_width = handler.bit_width();
-    let bit_height = handler.bit_height();
-    let arc_mutex = Arc::new(Mutex::new(&mut *handler));
-    let cache_hits = &AtomicU64::new(0);
-    match calculation_method {
-        CalculationMethod::CpuSingleThread => calculate_cpu_singlethread(
-            bit_width,
-            bit_height,
-            scaler_x,
-            scaler_y,
-            *threshhold,
-            is_pan,
-            cache,
-            cache_hits,
-            arc_mutex,
+    let mandelbrot_height = plot_mode.mandelbrot_height(bit_height);
+    let mapping = ParameterMapping {
+        pixel: match projection {
+            Projection::Linear => PixelMapping::Linear {
+                transform: viewport.transform(),
+                shift: viewport.lattice_shift(bit_width, mandelbrot_height),
+            },
+            Projection::LogPolar => PixelMapping::log_polar(bit_width, mandelbrot_height, viewport),
+        },
+        plane: *plane,
+    };
+    let auto = if *auto_threshhold { " (auto)" } else { "" };
+    #[cfg(feature = "gpu")]
+    let no_gpu = match gpu {
+        Err(reason) => format!(" (no Gpu: {reason})"),
+        Ok(gpu) if !gpu.f64 => " (Gpu without f64)".to_string(),
+        Ok(gpu) if !gpu.f64_matches_cpu() => " (Gpu's f64 differs from the CPU's)".to_string(),
+        Ok(_) => String::new(),
+    };
+    #[cfg(not(feature = "gpu"))]
+    let no_gpu = "";
+    let job = Job {
+        bit_width,
+        bit_height,
+        mandelbrot_height,
+        viewport: viewport.clone(),
+        mapping,
+        threshhold: *threshhold,
+        cache: cache.clone(),
+        orbits: orbits.clone(),
+        pool: pool.clone(),
+        #[cfg(feature = "gpu")]
+        throughput: throughput.clone(),
+        calibration: calibration.clone(),
+        calculation_method: *calculation_method,
+        settings: format!(
+            "threshhold={threshhold}{auto} zoom_factor={zoom_factor} {calculation_method:?}{no_gpu} {plot_mode:?} {projection:?} {plane:?}"
         ),
-        CalculationMethod::CpuMultiThread => calculate_cpu_multithread(
-            bit_width,
-            bit_height,
-            scaler_x,
-            scaler_y,
-            *threshhold,
-            is_pan,
-            cache,
-            cache_hits,
-            arc_mutex,
+    };
+    memory.worker.start(job);
+    handler.memory = Some(memory);
+}
+
+/// Starts rendering if any events have changed the view,
+/// and shows whatever the worker has finished since the last tick
+fn on_tick(handler: &mut sextant_terminal::Handler<Option<Memory>>, pixel_aspect: Option<f64>) {
+    if let Some(memory) = handler.memory.as_mut()
+        && memory.pending_render
+    {
+        memory.pending_render = false;
+        start_render(handler, pixel_aspect);
+    }
+    let Some(memory) = handler.memory.as_mut() else {
+        return;
+    };
+    let mut latest = None;
+    while let Some(frame) = memory.worker.try_recv() {
+        latest = Some(frame);
+    }
+    let Some(Frame {
+        bits,
+        title,
+        finished,
+    }) = latest
+    else {
+        return;
+    };
+    let mandelbrot_height = memory.plot_mode.mandelbrot_height(bits.height());
+    if finished && memory.auto_threshhold && mandelbrot_height > 0 {
+        let edge_fraction = bits.count_edge_zeros(mandelbrot_height) as f64
+            / (bits.width() * mandelbrot_height) as f64;
+        memory.auto_boost = next_auto_boost(memory.auto_boost, edge_fraction);
+    }
+    handler.bits = bits;
+    handler.render_bits().unwrap();
+    handler.set_title(title).unwrap();
+}
+
+/// Runs on the worker thread, sending a frame after each pass.
+/// Gives up without sending anything else as soon as `cancel` is set
+fn render(job: Job, cancel: &CancelToken, send: &dyn Fn(Frame)) {
+    let start = std::time::Instant::now();
+    let Job {
+        bit_width,
+        bit_height,
+        mandelbrot_height,
+        viewport,
+        mapping,
+        threshhold,
+        cache,
+        orbits,
+        pool,
+        #[cfg(feature = "gpu")]
+        throughput,
+        calibration,
+        calculation_method,
+        settings,
+    } = &job;
+    let (bit_width, bit_height, mandelbrot_height) = (*bit_width, *bit_height, *mandelbrot_height);
+    let mut backend_report = String::new();
+    #[cfg(feature = "gpu")]
+    let gpu_f64 = gpu().is_ok_and(|gpu| gpu.f64);
+    let calculation_method = &match calculation_method {
+        CalculationMethod::Auto => {
+            let mut calibration = calibration.lock().unwrap();
+            let scale = viewport.scale();
+            if calibration
+                .as_ref()
+                .is_none_or(|calibration| calibration.is_stale(*threshhold, scale))
+            {
+                let Some(method) = calibrate(
+                    mapping,
+                    bit_width,
+                    mandelbrot_height,
+                    *threshhold,
+                    pool,
+                    &available_backends(),
+                    cancel,
+                    #[cfg(feature = "gpu")]
+                    GpuPrecision::choose(
+                        gpu_f64,
+                        mapping.spacing(bit_width, mandelbrot_height),
+                        false,
+                    ),
+                ) else {
+                    return;
+                };
+                *calibration = Some(Calibration {
+                    threshhold: *threshhold,
+                    scale,
+                    method,
+                });
+            }
+            let method = calibration.as_ref().unwrap().method;
+            backend_report = format!(" backend={method:?}");
+            method
+        }
+        method => *method,
+    };
+    // Hybrid frames are put together from both, so they have to agree
+    #[cfg(feature = "gpu")]
+    let gpu_precision = GpuPrecision::choose(
+        gpu_f64,
+        mapping.spacing(bit_width, mandelbrot_height),
+        *calculation_method == CalculationMethod::Hybrid,
+    );
+    #[cfg(feature = "gpu")]
+    if let CalculationMethod::Gpu | CalculationMethod::Hybrid = calculation_method {
+        backend_report += &format!(" precision={gpu_precision:?}");
+    }
+    let mut bits = Bits2d::new(bit_width, bit_height);
+    // Points on the bifurcation diagram are drawn as cleared bits,
+    // the same as points inside the Mandelbrot set
+    if mandelbrot_height < bit_height {
+        bits.set_all_one();
+        let bifurcation_height = bit_height - mandelbrot_height;
+        match calculation_method {
+            CalculationMethod::CpuSingleThread => calculate_bifurcation_singlethread(
+                bit_width,
+                mandelbrot_height,
+                bifurcation_height,
+                viewport,
+                &mapping.plane,
+                *threshhold,
+                cancel,
+                &mut bits,
+            ),
+            _ => pool.install(|| {
+                calculate_bifurcation_multithread(
+                    bit_width,
+                    mandelbrot_height,
+                    bifurcation_height,
+                    viewport,
+                    &mapping.plane,
+                    *threshhold,
+                    cancel,
+                    &mut bits,
+                )
+            }),
+        }
+    }
+    // Pixels already computed by previous frames, which are skipped by every pass
+    let mut known = Bits2d::new(bit_width, mandelbrot_height);
+    let lattice = match mapping.pixel {
+        PixelMapping::Linear { shift, .. } => {
+            Some((LatticeKey::new(viewport.lattice(), mapping.plane), shift))
+        }
+        PixelMapping::LogPolar { .. } => None,
+    };
+    let mut seeded_iterations = vec![0; bit_width * mandelbrot_height];
+    let mut reused = match &lattice {
+        Some((key, shift)) => cache.lock().unwrap().seed(
+            key,
+            viewport.level,
+            *shift,
+            &mut bits,
+            &mut known,
+            &mut seeded_iterations,
         ),
-        CalculationMethod::Gpu => calculate_gpu(
+        None => 0,
+    };
+    // Where every pixel's orbit has got to, carried on from earlier frames of the same view
+    let mut view_orbits = orbits.lock().unwrap();
+    if view_orbits.iterations.len() != bit_width * mandelbrot_height {
+        view_orbits.iterations = vec![0; bit_width * mandelbrot_height];
+    }
+    // How many iterations each pixel took last frame, for estimating how long the tiles will take
+    let previous = view_orbits.iterations.clone();
+    if view_orbits.mapping.as_ref() != Some(mapping)
+        || view_orbits.orbits.len() != bit_width * mandelbrot_height
+    {
+        view_orbits.mapping = Some(mapping.clone());
+        view_orbits.orbits = vec![Orbit::default(); bit_width * mandelbrot_height];
+    }
+    for (i, orbit) in view_orbits.orbits.iter_mut().enumerate() {
+        let (px, py) = (i % bit_width, i / bit_width);
+        if known.get(px, py) == Some(true) {
+            let seeded = Orbit::from_result(bits.get(px, py).unwrap(), seeded_iterations[i]);
+            if orbit.iteration == 0 {
+                *orbit = seeded;
+            }
+            // Pixels cached with a lower cap that didn't escape have to carry on
+            if let Some(drawn) = seeded.drawn(*threshhold) {
+                bits.set(px, py, drawn);
+                continue;
+            }
+            known.set(px, py, false);
+            reused -= 1;
+        }
+        if let Some(drawn) = orbit.drawn(*threshhold) {
+            bits.set(px, py, drawn);
+            known.set(px, py, true);
+            reused += 1;
+        }
+    }
+    // Rows made of the complex conjugates of another row's points have the same values,
+    // so they're copied from it after each pass instead of being computed
+    let mirrors = mirror_rows(mapping, bit_width, mandelbrot_height);
+    let mut skip = known.clone();
+    let mut mirrored = 0;
+    for (py, source) in mirrors.iter().enumerate() {
+        if source.is_none() {
+            continue;
+        }
+        for px in 0..bit_width {
+            if skip.get(px, py) == Some(false) {
+                skip.set(px, py, true);
+                mirrored += 1;
+            }
+        }
+    }
+    let (order, costs) = schedule(bit_width, mandelbrot_height, &skip, &previous);
+    let mut tile_times = vec![std::time::Duration::ZERO; costs.len()];
+    #[cfg(feature = "gpu")]
+    let split = {
+        let (cpu_rate, gpu_rate) = match calculation_method {
+            CalculationMethod::Hybrid => throughput.lock().unwrap().rates(),
+            CalculationMethod::Gpu => (0.0, 1.0),
+            CalculationMethod::CpuSingleThread
+            | CalculationMethod::CpuMultiThread
+            | CalculationMethod::Auto => (1.0, 0.0),
+        };
+        split_tiles(&order, &costs, cpu_rate, gpu_rate)
+    };
+    #[cfg(feature = "gpu")]
+    let cpu_order = &split.cpu_order;
+    // Without the GPU the threads get every tile
+    #[cfg(not(feature = "gpu"))]
+    let cpu_order = &order;
+    let mut multithread_time = std::time::Duration::ZERO;
+    #[cfg(feature = "gpu")]
+    let mut gpu_time = std::time::Duration::ZERO;
+    // A pass over the threads' tiles, timing them for the title
+    let mut multithread = |stride, orbits: &mut [Orbit], bits: &mut Bits2d| {
+        let pass_start = std::time::Instant::now();
+        let times = calculate_cpu_multithread(
             bit_width,
-            bit_height,
-            scaler_x,
-            scaler_y,
+            mandelbrot_height,
+            stride,
+            mapping,
             *threshhold,
-            arc_mutex,
-        ),
+            &skip,
+            orbits,
+            cpu_order,
+            pool,
+            cancel,
+            bits,
+        );
+        multithread_time += pass_start.elapsed();
+        for (total, time) in tile_times.iter_mut().zip(times) {
+            *total += time;
+        }
+    };
+    if mandelbrot_height > 0 && bit_width > 0 {
+        for (i, &stride) in PASS_STRIDES.iter().enumerate() {
+            if cancel.is_cancelled() {
+                return;
+            }
+            match calculation_method {
+                CalculationMethod::CpuSingleThread => calculate_cpu_singlethread(
+                    bit_width,
+                    mandelbrot_height,
+                    stride,
+                    mapping,
+                    *threshhold,
+                    &skip,
+                    &mut view_orbits.orbits,
+                    cancel,
+                    &mut bits,
+                ),
+                CalculationMethod::CpuMultiThread | CalculationMethod::Auto => {
+                    multithread(stride, &mut view_orbits.orbits, &mut bits)
+                }
+                #[cfg(feature = "gpu")]
+                CalculationMethod::Hybrid => {
+                    // The GPU works through its tiles on another thread at the same time
+                    let gpu_output = std::thread::scope(|scope| {
+                        let gpu = split.gpu_tiles.contains(&1).then(|| {
+                            scope.spawn(|| {
+                                let gpu_start = std::time::Instant::now();
+                                let output = calculate_gpu(
+                                    bit_width,
+                                    mandelbrot_height,
+                                    stride,
+                                    mapping,
+                                    *threshhold,
+                                    &split.gpu_tiles,
+                                    &skip,
+                                    gpu_precision,
+                                );
+                                (output, gpu_start.elapsed())
+                            })
+                        });
+                        multithread(stride, &mut view_orbits.orbits, &mut bits);
+                        gpu.map(|gpu| gpu.join().unwrap())
+                    });
+                    if let Some((output, time)) = gpu_output {
+                        gpu_time += time;
+                        merge_gpu_output(
+                            &output,
+                            *threshhold,
+                            gpu_precision,
+                            &mut bits,
+                            &mut view_orbits,
+                        );
+                    }
+                }
+                #[cfg(feature = "gpu")]
+                CalculationMethod::Gpu => {
+                    let output = calculate_gpu(
+                        bit_width,
+                        mandelbrot_height,
+                        stride,
+                        mapping,
+                        *threshhold,
+                        &split.gpu_tiles,
+                        &skip,
+                        gpu_precision,
+                    );
+                    merge_gpu_output(
+                        &output,
+                        *threshhold,
+                        gpu_precision,
+                        &mut bits,
+                        &mut view_orbits,
+                    );
+                }
+            }
+            copy_mirrored_rows(&mirrors, &known, &mut bits, &mut view_orbits.orbits);
+            if stride > 1 && !cancel.is_cancelled() {
+                bits.fill_from_grid(stride, mandelbrot_height, &known);
+                send(Frame {
+                    bits: bits.clone(),
+                    title: format!("Calculating... (pass {}/{})", i + 2, PASS_STRIDES.len()),
+                    finished: false,
+                });
+            }
+        }
     }
-    handler.render_bits().unwrap();
-    handler
-        .set_title(format!(
-            "Finished processing in {:?} threshhold={threshhold} cache_hits={}/{} {calculation_method:?}",
+    // The GPU's tiles got theirs from `merge_gpu_output`
+    let ViewOrbits {
+        orbits, iterations, ..
+    } = &mut *view_orbits;
+    for (i, orbit) in orbits.iter().enumerate() {
+        #[cfg(feature = "gpu")]
+        {
+            let (px, py) = (i % bit_width, i / bit_width);
+            let tile = py / SCHEDULE_TILE * bit_width.div_ceil(SCHEDULE_TILE) + px / SCHEDULE_TILE;
+            if split.gpu_tiles[tile] == 1 {
+                continue;
+            }
+        }
+        if orbit.iteration > 0 {
+            iterations[i] = orbit.iteration;
+        }
+    }
+    // Stored in the cache with the pixels, so frames with other caps can be seeded from them
+    let cached_iterations: Vec<usize> = match lattice {
+        Some(_) => orbits.iter().map(|orbit| orbit.iteration).collect(),
+        None => Vec::new(),
+    };
+    drop(view_orbits);
+    if cancel.is_cancelled() {
+        return;
+    }
+    // The GPU's results at less than f64 can differ from the other methods',
+    // so they're kept out of the cache the other methods read from
+    #[cfg(feature = "gpu")]
+    let lattice = lattice.filter(|_| {
+        gpu_precision == GpuPrecision::F64
+            || !matches!(
+                calculation_method,
+                CalculationMethod::Gpu | CalculationMethod::Hybrid
+            )
+    });
+    let mut cache = cache.lock().unwrap();
+    if let Some((key, shift)) = lattice {
+        cache.store(
+            &key,
+            viewport.level,
+            shift,
+            &bits,
+            &cached_iterations,
+            mandelbrot_height,
+        );
+    }
+    // How well the tiles were spread over the threads
+    let schedule_report = if multithread_time.is_zero() {
+        String::new()
+    } else {
+        let busy: std::time::Duration = tile_times.iter().sum();
+        format!(
+            " schedule={}tiles/{}threads slowest_tile={:?} speedup={:.1}x",
+            cpu_order.len(),
+            pool.current_num_threads(),
+            tile_times.iter().max().unwrap(),
+            busy.as_secs_f64() / multithread_time.as_secs_f64()
+        )
+    };
+    // and how much of the work the GPU got
+    #[cfg(feature = "gpu")]
+    let schedule_report = match calculation_method {
+        CalculationMethod::Hybrid => {
+            let mut throughput = throughput.lock().unwrap();
+            throughput.measure(split.cpu_cost, multithread_time, split.gpu_cost, gpu_time);
+            let total = split.cpu_cost + split.gpu_cost;
+            if total > 0.0 {
+                schedule_report + &format!(" gpu_share={:.0}%", 100.0 * split.gpu_cost / total)
+            } else {
+                schedule_report
+            }
+        }
+        _ => schedule_report,
+    };
+    send(Frame {
+        title: format!(
+            "Finished processing in {:?} reused={reused}/{} mirrored={mirrored} tiles={}{schedule_report} {settings}{backend_report}",
             start.elapsed(),
-            cache_hits.load(atomic::Ordering::Relaxed),
-            handler.bit_area()
-        ))
-        .unwrap();
-    handler.memory = Some(memory);
-    false
+            known.area(),
+            cache.len()
+        ),
+        bits,
+        finished: true,
+    });
 }
 
-#[derive(Debug)]
-struct Scaler {
-    original_min: f64,
-    original_max: f64,
-    target_min: f64,
-    target_max: f64,
-    scalar: f64,
+/// For each row of a linear, unrotated view,
+/// the row whose points are the complex conjugates of its points if there is one
+/// and it's on the side of the real axis with more rows.
+/// All of the planes map conjugates onto conjugates, and conjugate c's have conjugate orbits
+fn mirror_rows(mapping: &ParameterMapping, width: usize, height: usize) -> Vec<Option<usize>> {
+    let mut mirrors = vec![None; height];
+    let PixelMapping::Linear {
+        transform: Transform([[_, b, _], [d, _, _]]),
+        ..
+    } = mapping.pixel
+    else {
+        return mirrors;
+    };
+    if width == 0 || b != 0.0 || d != 0.0 {
+        return mirrors;
+    }
+    let ys: Vec<f64> = (0..height).map(|py| mapping.pixel.map(0, py).1).collect();
+    let rows: std::collections::HashMap<u64, usize> = ys
+        .iter()
+        .enumerate()
+        .map(|(py, y)| (y.to_bits(), py))
+        .collect();
+    let above = ys.iter().filter(|&&y| y > 0.0).count();
+    let below = ys.iter().filter(|&&y| y < 0.0).count();
+    for (py, y) in ys.iter().enumerate() {
+        let smaller_half = if above >= below { *y < 0.0 } else { *y > 0.0 };
+        if smaller_half {
+            mirrors[py] = rows.get(&(-y).to_bits()).copied();
+        }
+    }
+    mirrors
+}
+
+/// Copies the rows picked by `mirror_rows` from their conjugates, except for known pixels
+fn copy_mirrored_rows(
+    mirrors: &[Option<usize>],
+    known: &Bits2d,
+    bits: &mut Bits2d,
+    orbits: &mut [Orbit],
+) {
+    let width = known.width();
+    for (py, source) in mirrors.iter().enumerate() {
+        let Some(source) = *source else {
+            continue;
+        };
+        for px in 0..width {
+            if known.get(px, py) == Some(true) {
+                continue;
+            }
+            bits.set(px, py, bits.get(px, source).unwrap());
+            let orbit = orbits[source * width + px];
+            orbits[py * width + px] = Orbit {
+                y: -orbit.y,
+                ..orbit
+            };
+        }
+    }
 }
 
-impl Scaler {
-    fn new(original_min: f64, original_max: f64, target_min: f64, target_max: f64) -> Scaler {
-        let original_range = original_max - original_min;
-        let target_range = target_max - target_min;
-        let scalar = target_range / original_range;
+/// Merges a pass of the GPU's into `bits`, and the iteration counts it reads back with the last pass
+/// into `view_orbits`: as estimates, and as orbits too if they're the same as the CPU's would be
+#[cfg(feature = "gpu")]
+fn merge_gpu_output(
+    output: &GpuOutput,
+    threshhold: usize,
+    precision: GpuPrecision,
+    bits: &mut Bits2d,
+    view_orbits: &mut ViewOrbits,
+) {
+    let mut counts = vec![0; view_orbits.iterations.len()];
+    write_gpu_output(output, bits, &mut counts);
+    for (i, &n) in counts.iter().enumerate() {
+        if n == 0 {
+            continue;
+        }
+        view_orbits.iterations[i] = n;
+        if precision == GpuPrecision::F64 {
+            view_orbits.orbits[i] = Orbit::from_result(n < threshhold, n);
+        }
+    }
+}
 
-        Self {
-            original_min,
-            original_max,
-            target_min,
-            target_max,
-            scalar,
-        }
-    }
-    fn scale(&self, mut n: f64) -> f64 {
-        n -= &self.original_min;
-        n *= &self.scalar;
-        n += &self.target_min;
-        n
-    }
-
-    fn offset(&mut self, amount: f64) {
-        *self = Scaler::new(
-            self.original_min,
-            self.original_max,
-            self.target_min + amount,
-            self.target_max + amount,
-        );
+/// Where the orbit of 0 under z²+c has got to
+#[derive(Clone, Copy, Debug, Default)]
+struct Orbit {
+    x: f64,
+    y: f64,
+    iteration: usize,
+}
+
+impl Orbit {
+    /// Stands in for an orbit that's only known to have escaped after `iteration` iterations,
+    /// or not to have after that many. It answers `drawn` the same way,
+    /// and if it has to carry on, `iterate` starts it over
+    fn from_result(escaped: bool, iteration: usize) -> Self {
+        let x = if escaped { f64::INFINITY } else { 0.0 };
+        Orbit {
+            x,
+            y: 0.0,
+            iteration,
+        }
     }
-    fn zoom_in(&mut self) {
-        *self = Scaler::new(
-            self.original_min,
-            self.original_max,
-            self.target_min + (self.scalar / 2.0),
-            self.target_max - (self.scalar / 2.0),
-        );
+
+    /// Carries on until the orbit escapes or has had `threshhold` iterations
+    fn iterate(&mut self, x0: f64, y0: f64, threshhold: usize) {
+        let Orbit {
+            mut x,
+            mut y,
+            mut iteration,
+        } = *self;
+        // An orbit that comes back to 0 goes round the same points forever, so starting over
+        // gets the same result, and it's how the ones from `from_result` find their points again
+        if x == 0.0 && y == 0.0 {
+            iteration = 0;
+        }
+        let mut x2 = x * x;
+        let mut y2 = y * y;
+        while (x2 + y2 <= 4.0) && (iteration < threshhold) {
+            y = (x + x) * y + y0;
+            x = x2 - y2 + x0;
+            x2 = x * x;
+            y2 = y * y;
+            iteration += 1;
+        }
+        *self = Orbit { x, y, iteration };
     }
-    fn zoom_out(&mut self) {
-        *self = Scaler::new(
-            self.original_min,
-            self.original_max,
-            self.target_min - (self.scalar / 2.0),
-            self.target_max + (self.scalar / 2.0),
-        );
+
+    /// Whether the pixel is drawn with a cap of `threshhold`,
+    /// or `None` if the orbit needs to carry on to find out.
+    /// Uses the same test as `iterate`, so NaN orbits count as escaped like they stopped it
+    fn drawn(&self, threshhold: usize) -> Option<bool> {
+        if self.x * self.x + self.y * self.y <= 4.0 {
+            (self.iteration >= threshhold).then_some(false)
+        } else {
+            Some(self.iteration < threshhold)
+        }
     }
 }
 
+/// The orbits of every Mandelbrot pixel of the view being rendered,
+/// so changing only the cap doesn't start them over.
+/// Orbits are only ever carried on, so they stay valid even if a render is cancelled
+#[derive(Debug, Default)]
+struct ViewOrbits {
+    mapping: Option<ParameterMapping>,
+    orbits: Vec<Orbit>,
+    /// How many iterations each pixel took the last time it was computed, by any method.
+    /// Kept when the view moves, as an estimate for the pixels that end up in the same place
+    iterations: Vec<usize>,
+}
+
+/// Starts at 200 for the whole set and adds 100 for every time the view has been zoomed in 2x
+fn auto_threshhold(home_scale: f64, scale: f64, boost: f64) -> usize {
+    let depth = (home_scale / scale).log2().max(0.0);
+    ((200.0 + 100.0 * depth) * boost) as usize
+}
+
+/// A pixel that hit the cap right next to one that escaped is probably a slow escaper,
+/// so lots of those means detail is being cut off by the cap
+fn next_auto_boost(boost: f64, edge_fraction: f64) -> f64 {
+    if edge_fraction > 0.02 {
+        boost * 1.5
+    } else if edge_fraction < 0.005 {
+        (boost / 1.25).max(1.0)
+    } else {
+        boost
+    }
+}
+
+/// Progressive rendering first computes every pixel whose coordinates are multiples of 8,
+/// then the ones that are multiples of 4 that haven't been computed yet, and so on.
+/// The screen is shown after each pass, with the pixels in between filled in
+const PASS_STRIDES: [usize; 4] = [8, 4, 2, 1];
+
+/// Whether the pass with `stride` computes this pixel
+fn in_pass(px: usize, py: usize, stride: usize) -> bool {
+    let on_grid = |stride: usize| px.is_multiple_of(stride) && py.is_multiple_of(stride);
+    on_grid(stride) && (stride == PASS_STRIDES[0] || !on_grid(stride * 2))
+}
+
+/// Maps pixel coordinates to points on the plane
+#[derive(Clone, Debug, PartialEq)]
+enum PixelMapping {
+    Linear {
+        /// From lattice coordinates
+        transform: Transform,
+        /// Lattice coordinates of pixel `(0, 0)`
+        shift: (i64, i64),
+    },
+    LogPolar {
+        center_x: f64,
+        center_y: f64,
+        /// Distance from the center for each row
+        radii: Vec<f64>,
+        /// (cos, sin) of the angle for each column
+        directions: Vec<(f64, f64)>,
+    },
+}
+
+impl PixelMapping {
+    /// The angle goes once around the center across the width,
+    /// and each row steps the log radius by the same amount as each column steps the angle
+    /// (times the pixel aspect), so shapes keep their proportions.
+    /// The top row is at the corners of the linear view, and each row below it zooms further in.
+    /// The left column is at the view's rotation
+    fn log_polar(width: usize, height: usize, viewport: &Viewport) -> PixelMapping {
+        let (center_x, center_y) = viewport.center();
+        let top_radius =
+            viewport.scale() * (width as f64 / 2.0).hypot(viewport.aspect * height as f64 / 2.0);
+        let step = std::f64::consts::TAU / width as f64;
+        let radii = (0..height)
+            .map(|py| top_radius * (-step * viewport.aspect * py as f64).exp())
+            .collect();
+        let directions = (0..width)
+            .map(|px| {
+                let (sin, cos) = (viewport.rotation + step * px as f64).sin_cos();
+                (cos, sin)
+            })
+            .collect();
+        PixelMapping::LogPolar {
+            center_x,
+            center_y,
+            radii,
+            directions,
+        }
+    }
+
+    /// The `width` by `height` part of the screen starting at pixel `(left, top)`
+    fn crop(&self, left: usize, top: usize, width: usize, height: usize) -> PixelMapping {
+        match self {
+            PixelMapping::Linear { transform, shift } => PixelMapping::Linear {
+                transform: *transform,
+                shift: (shift.0 + left as i64, shift.1 + top as i64),
+            },
+            PixelMapping::LogPolar {
+                center_x,
+                center_y,
+                radii,
+                directions,
+            } => PixelMapping::LogPolar {
+                center_x: *center_x,
+                center_y: *center_y,
+                radii: radii[top..top + height].to_vec(),
+                directions: directions[left..left + width].to_vec(),
+            },
+        }
+    }
+
+    fn map(&self, px: usize, py: usize) -> (f64, f64) {
+        match self {
+            PixelMapping::Linear { transform, shift } => {
+                transform.apply((px as i64 + shift.0) as f64, (py as i64 + shift.1) as f64)
+            }
+            PixelMapping::LogPolar {
+                center_x,
+                center_y,
+                radii,
+                directions,
+            } => {
+                let (cos, sin) = directions[px];
+                (center_x + radii[py] * cos, center_y + radii[py] * sin)
+            }
+        }
+    }
+}
+
+/// Maps pixel coordinates to the c of z²+c
+#[derive(Clone, Debug, PartialEq)]
+struct ParameterMapping {
+    pixel: PixelMapping,
+    plane: ParameterPlane,
+}
+
+impl ParameterMapping {
+    fn map(&self, px: usize, py: usize) -> (f64, f64) {
+        let (x, y) = self.pixel.map(px, py);
+        self.plane.to_c(x, y)
+    }
+
+    /// Smallest distance between the c of neighboring pixels on a `width` by `height` screen,
+    /// sampled every `SCHEDULE_TILE` pixels and along the right and bottom edges.
+    /// Unlike the viewport's scale, this counts how `plane` stretches the screen
+    #[cfg(feature = "gpu")]
+    fn spacing(&self, width: usize, height: usize) -> f64 {
+        let samples = |len: usize| (0..len - 1).step_by(SCHEDULE_TILE).chain([len - 2]);
+        let mut spacing = f64::INFINITY;
+        for py in samples(height.max(2)) {
+            for px in samples(width.max(2)) {
+                let (x, y) = self.map(px, py);
+                for (nx, ny) in [self.map(px + 1, py), self.map(px, py + 1)] {
+                    spacing = spacing.min((nx - x).hypot(ny - y));
+                }
+            }
+        }
+        spacing
+    }
+}
+
+/// Computes the pixels of a row starting at column `left`
 fn calculate_cpu_inner(
+    left: usize,
     py: usize,
-    width: usize,
-    scaler_x: &Scaler,
-    scaler_y: &Scaler,
+    stride: usize,
+    mapping: &ParameterMapping,
     threshhold: usize,
-    is_pan: bool,
-    cache: &DashMap<(HashableF64, HashableF64, usize), bool>,
-    cache_hits: &AtomicU64,
-    handler: Arc<Mutex<&mut sextant_terminal::Handler<Option<Memory>>>>,
+    skip: &Bits2d,
+    orbits: &mut [Orbit],
+    cancel: &CancelToken,
+    mut row: RowMut,
 ) {
-    let y0 = scaler_y.scale(py as f64);
-    for px in 0..width {
-        let x0 = scaler_x.scale(px as f64);
-        let key = (HashableF64(x0), HashableF64(y0), threshhold);
-        let calculate_b = || {
-            let mut x = 0.0;
-            let mut y = 0.0;
-            let mut x2 = 0.0;
-            let mut y2 = 0.0;
-            let mut iteration = 0;
-            while (x2 + y2 <= 4.0) && (iteration < threshhold) {
-                y = (x + x) * y + y0;
-                x = x2 - y2 + x0;
-                x2 = x * x;
-                y2 = y * y;
-                iteration += 1;
+    for x in (0..orbits.len()).step_by(stride) {
+        if cancel.is_cancelled() {
+            return;
+        }
+        let px = left + x;
+        if !in_pass(px, py, stride) || skip.get(px, py) == Some(true) {
+            continue;
+        }
+        let (x0, y0) = mapping.map(px, py);
+        let orbit = &mut orbits[x];
+        orbit.iterate(x0, y0, threshhold);
+        row.set(x, orbit.drawn(threshhold).unwrap());
+    }
+}
+
+/// Width and height in pixels of the tiles `calculate_cpu_multithread` hands out.
+/// A multiple of 32 so that tiles start on a word of `Bits2d`
+const SCHEDULE_TILE: usize = 32;
+
+/// Tile indices of a `width` by `height` screen from the most to the least expensive,
+/// and the estimated cost of each tile.
+/// Each pixel that isn't skipped is estimated to cost as many iterations as it took last frame
+fn schedule(
+    width: usize,
+    height: usize,
+    skip: &Bits2d,
+    previous: &[usize],
+) -> (Vec<usize>, Vec<usize>) {
+    let across = width.div_ceil(SCHEDULE_TILE);
+    let mut costs = vec![0; across * height.div_ceil(SCHEDULE_TILE)];
+    for py in 0..height {
+        for px in 0..width {
+            if skip.get(px, py) == Some(false) {
+                let cost = previous
+                    .get(py * width + px)
+                    .map_or(1, |&iterations| iterations.max(1));
+                costs[py / SCHEDULE_TILE * across + px / SCHEDULE_TILE] += cost;
             }
-            iteration == threshhold
-        };
+        }
+    }
+    let mut order: Vec<usize> = (0..costs.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(costs[i]));
+    (order, costs)
+}
 
-        // When zooming, the number of cache hits is usually 0 or 1,
-        // not worth spending time hashing for.
-        // However, there are MANY cache hits when panning.
-        // Due to doing it this way,
-        // the first pan of a zoom will not have any cache hits,
-        // but all subsequent ones will
-        let b = if is_pan {
-            match cache.get(&key) {
-                Some(b) => {
-                    cache_hits.fetch_add(1, atomic::Ordering::Relaxed);
-                    *b.value()
+/// What `CalculationMethod::Auto` picked, and for what
+#[derive(Debug)]
+struct Calibration {
+    threshhold: usize,
+    scale: f64,
+    method: CalculationMethod,
+}
+
+impl Calibration {
+    /// Whether the cap has doubled or halved, or the view has zoomed 16x either way, since it was
+    /// picked. Both change how much work each pixel is and how it's spread over the screen,
+    /// which is what decides whether the threads or the GPU come out ahead
+    fn is_stale(&self, threshhold: usize, scale: f64) -> bool {
+        let cap = threshhold as f64 / self.threshhold as f64;
+        let levels = (scale / self.scale).log2().abs();
+        !(0.5..=2.0).contains(&cap) || levels >= 4.0
+    }
+}
+
+/// Size of the part in the middle of the screen that `calibrate` renders
+const CALIBRATION_SIZE: (usize, usize) = (128, 64);
+
+/// Most iterations an orbit takes in `calibrate`,
+/// so deep views with high caps don't hold up their first frame for long
+const CALIBRATION_THRESHHOLD: usize = 1000;
+
+/// Which of `backends` renders the middle of the view the fastest,
+/// out of `CpuSingleThread`, `CpuMultiThread` and `Gpu`,
+/// or `None` if it's cancelled before they've all been timed
+fn calibrate(
+    mapping: &ParameterMapping,
+    width: usize,
+    height: usize,
+    threshhold: usize,
+    pool: &rayon::ThreadPool,
+    backends: &[CalculationMethod],
+    cancel: &CancelToken,
+    #[cfg(feature = "gpu")] gpu_precision: GpuPrecision,
+) -> Option<CalculationMethod> {
+    let threshhold = threshhold.min(CALIBRATION_THRESHHOLD);
+    let (sample_width, sample_height) = (
+        width.min(CALIBRATION_SIZE.0),
+        height.min(CALIBRATION_SIZE.1),
+    );
+    let mapping = ParameterMapping {
+        pixel: mapping.pixel.crop(
+            (width - sample_width) / 2,
+            (height - sample_height) / 2,
+            sample_width,
+            sample_height,
+        ),
+        plane: mapping.plane,
+    };
+    let skip = Bits2d::new(sample_width, sample_height);
+    let (order, _) = schedule(sample_width, sample_height, &skip, &[]);
+    #[cfg(feature = "gpu")]
+    let all_tiles = vec![1; order.len()];
+    #[cfg(feature = "gpu")]
+    if backends.contains(&CalculationMethod::Gpu) {
+        // Setting up the device, compiling the shaders and making the buffers for this size
+        // only happen once, so they shouldn't count against it
+        calculate_gpu(
+            sample_width,
+            sample_height,
+            PASS_STRIDES[0],
+            &mapping,
+            1,
+            &all_tiles,
+            &skip,
+            gpu_precision,
+        );
+    }
+    let time = |method: CalculationMethod| {
+        let mut bits = Bits2d::new(sample_width, sample_height);
+        let mut orbits = vec![Orbit::default(); sample_width * sample_height];
+        let start = std::time::Instant::now();
+        for stride in PASS_STRIDES {
+            if cancel.is_cancelled() {
+                break;
+            }
+            match method {
+                CalculationMethod::CpuSingleThread => calculate_cpu_singlethread(
+                    sample_width,
+                    sample_height,
+                    stride,
+                    &mapping,
+                    threshhold,
+                    &skip,
+                    &mut orbits,
+                    cancel,
+                    &mut bits,
+                ),
+                CalculationMethod::CpuMultiThread => {
+                    calculate_cpu_multithread(
+                        sample_width,
+                        sample_height,
+                        stride,
+                        &mapping,
+                        threshhold,
+                        &skip,
+                        &mut orbits,
+                        &order,
+                        pool,
+                        cancel,
+                        &mut bits,
+                    );
                 }
-                None => {
-                    let b = calculate_b();
-                    cache.insert(key, b);
-                    b
+                #[cfg(feature = "gpu")]
+                CalculationMethod::Gpu => {
+                    calculate_gpu(
+                        sample_width,
+                        sample_height,
+                        stride,
+                        &mapping,
+                        threshhold,
+                        &all_tiles,
+                        &skip,
+                        gpu_precision,
+                    );
                 }
+                #[cfg(feature = "gpu")]
+                CalculationMethod::Hybrid => unreachable!("{method:?} isn't calibrated"),
+                CalculationMethod::Auto => unreachable!("{method:?} isn't calibrated"),
             }
-        } else {
-            cache.clear();
-            calculate_b()
-        };
+        }
+        start.elapsed()
+    };
+    let fastest = backends
+        .iter()
+        .copied()
+        .min_by_key(|&method| time(method))
+        .unwrap_or(CalculationMethod::CpuSingleThread);
+    // The ones that were cut short would look faster than they are
+    (!cancel.is_cancelled()).then_some(fastest)
+}
 
-        let mut lock = handler.lock().unwrap();
-        lock.set_bit(px, py, !b);
+/// The methods `calibrate` can pick from on this machine
+fn available_backends() -> Vec<CalculationMethod> {
+    #[cfg_attr(not(feature = "gpu"), allow(unused_mut))]
+    let mut backends = vec![
+        CalculationMethod::CpuSingleThread,
+        CalculationMethod::CpuMultiThread,
+    ];
+    #[cfg(feature = "gpu")]
+    if gpu().is_ok() {
+        backends.push(CalculationMethod::Gpu);
     }
+    backends
 }
 
+/// Every thread of `pool` keeps taking the most expensive tile left in `order` until there are none,
+/// so the slow tiles get started first and the cheap ones fill in the gaps at the end.
+/// Returns how long each tile took, by tile index
 fn calculate_cpu_multithread(
     width: usize,
     height: usize,
-    scaler_x: &Scaler,
-    scaler_y: &Scaler,
+    stride: usize,
+    mapping: &ParameterMapping,
     threshhold: usize,
-    is_pan: bool,
-    cache: &DashMap<(HashableF64, HashableF64, usize), bool>,
-    cache_hits: &AtomicU64,
-    handler: Arc<Mutex<&mut sextant_terminal::Handler<Option<Memory>>>>,
-) {
-    (0..height).into_par_iter().for_each(move |py| {
-        calculate_cpu_inner(
-            py,
-            width,
-            scaler_x,
-            scaler_y,
-            threshhold,
-            is_pan,
-            cache,
-            cache_hits,
-            handler.clone(),
-        );
+    skip: &Bits2d,
+    orbits: &mut [Orbit],
+    order: &[usize],
+    pool: &rayon::ThreadPool,
+    cancel: &CancelToken,
+    bits: &mut Bits2d,
+) -> Vec<std::time::Duration> {
+    let across = width.div_ceil(SCHEDULE_TILE);
+    let mut orbit_tiles: Vec<Vec<&mut [Orbit]>> = (0..across * height.div_ceil(SCHEDULE_TILE))
+        .map(|_| Vec::new())
+        .collect();
+    for (py, row) in orbits.chunks_mut(width).enumerate() {
+        for (x, orbits) in row.chunks_mut(SCHEDULE_TILE).enumerate() {
+            orbit_tiles[py / SCHEDULE_TILE * across + x].push(orbits);
+        }
+    }
+    let mut tiles: Vec<_> = bits
+        .tiles_mut(SCHEDULE_TILE / 32, SCHEDULE_TILE)
+        .into_iter()
+        .zip(orbit_tiles)
+        .map(Some)
+        .collect();
+    let queue = order
+        .iter()
+        .map(|&i| (i, tiles[i].take().unwrap()))
+        .collect();
+    work_through(pool, queue, tiles.len(), |(tile, orbits)| {
+        for ((y, row), orbits) in tile.rows.into_iter().enumerate().zip(orbits) {
+            calculate_cpu_inner(
+                tile.left,
+                tile.top + y,
+                stride,
+                mapping,
+                threshhold,
+                skip,
+                orbits,
+                cancel,
+                row,
+            );
+        }
     })
 }
 
+/// Every thread of `pool` keeps taking the next item of `queue` and calling `work` on it
+/// until there are none left. Returns how long each item took, by the index it came with,
+/// out of `len`
+fn work_through<T: Send>(
+    pool: &rayon::ThreadPool,
+    queue: Vec<(usize, T)>,
+    len: usize,
+    work: impl Fn(T) + Sync,
+) -> Vec<std::time::Duration> {
+    // Popped from the back
+    let queue = Mutex::new(queue.into_iter().rev().collect::<Vec<_>>());
+    let finished = pool.broadcast(|_| {
+        let mut finished = Vec::new();
+        loop {
+            // Popped on its own so the lock is released before the work starts
+            let next = queue.lock().unwrap().pop();
+            let Some((i, item)) = next else {
+                break;
+            };
+            let start = std::time::Instant::now();
+            work(item);
+            finished.push((i, start.elapsed()));
+        }
+        finished
+    });
+    let mut times = vec![std::time::Duration::ZERO; len];
+    for (i, time) in finished.into_iter().flatten() {
+        times[i] = time;
+    }
+    times
+}
+
 fn calculate_cpu_singlethread(
     width: usize,
     height: usize,
-    scaler_x: &Scaler,
-    scaler_y: &Scaler,
+    stride: usize,
+    mapping: &ParameterMapping,
     threshhold: usize,
-    is_pan: bool,
-    cache: &DashMap<(HashableF64, HashableF64, usize), bool>,
-    cache_hits: &AtomicU64,
-    handler: Arc<Mutex<&mut sextant_terminal::Handler<Option<Memory>>>>,
+    skip: &Bits2d,
+    orbits: &mut [Orbit],
+    cancel: &CancelToken,
+    bits: &mut Bits2d,
 ) {
-    (0..height).for_each(move |py| {
-        calculate_cpu_inner(
-            py,
-            width,
-            scaler_x,
-            scaler_y,
-            threshhold,
-            is_pan,
-            cache,
-            cache_hits,
-            handler.clone(),
-        );
-    })
+    bits.rows_mut()
+        .into_iter()
+        .zip(orbits.chunks_mut(width))
+        .take(height)
+        .enumerate()
+        .step_by(stride)
+        .for_each(|(py, (row, orbits))| {
+            calculate_cpu_inner(
+                0, py, stride, mapping, threshhold, skip, orbits, cancel, row,
+            );
+        })
 }
 
-fn calculate_gpu(
+/// The rows of the attractor of the real orbit of z²+c for the c of column `px`
+/// in a diagram `height` rows tall, with z going from 2 at the top to -2 at the bottom.
+/// The x axis is the same as the unrotated view's
+fn calculate_bifurcation_inner(
+    px: usize,
     width: usize,
     height: usize,
-    scaler_x: &Scaler,
-    scaler_y: &Scaler,
+    viewport: &Viewport,
+    plane: &ParameterPlane,
     threshhold: usize,
-    handler: Arc<Mutex<&mut sextant_terminal::Handler<Option<Memory>>>>,
+    cancel: &CancelToken,
+) -> Vec<usize> {
+    let mut rows = Vec::new();
+    if cancel.is_cancelled() {
+        return rows;
+    }
+    let x = viewport.center().0 + viewport.scale() * (px as f64 - (width / 2) as f64);
+    // All of the planes map the real axis onto the real axis
+    let (c, _) = plane.to_c(x, 0.0);
+    let mut z = 0.0;
+    // Let the orbit settle onto its attractor before plotting it
+    for _ in 0..threshhold {
+        z = z * z + c;
+        if z.abs() > 2.0 {
+            return rows;
+        }
+    }
+    for _ in 0..threshhold {
+        z = z * z + c;
+        if z.abs() > 2.0 {
+            rows.clear();
+            return rows;
+        }
+        let row = ((2.0 - z) / 4.0 * height as f64) as usize;
+        if row < height {
+            rows.push(row);
+        }
+    }
+    rows
+}
+
+/// Plots the bifurcation diagram into the rows `top..top+height`
+fn calculate_bifurcation_multithread(
+    width: usize,
+    top: usize,
+    height: usize,
+    viewport: &Viewport,
+    plane: &ParameterPlane,
+    threshhold: usize,
+    cancel: &CancelToken,
+    bits: &mut Bits2d,
 ) {
-    use std::sync::LazyLock;
-
-    use wgpu::{BufferUsages, Instance};
-    static INSTANCE: LazyLock<Instance> =
-        LazyLock::new(|| Instance::new(wgpu::InstanceDescriptor::new_without_display_handle()));
-    static ADAPTER: LazyLock<wgpu::Adapter> = LazyLock::new(|| {
-        INSTANCE
-            .request_adapter(&Default::default())
-            .block_on()
-            .unwrap()
-    });
-    let device_queue: LazyLock<(wgpu::Device, wgpu::Queue)> = LazyLock::new(|| {
-        ADAPTER
-            .request_device(&wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::SHADER_F64,
-                ..Default::default()
-            })
-            .block_on()
-            .unwrap()
-    });
-    let (device, queue) = &*device_queue;
-
-    let Scaler {
-        original_min: original_min_x,
-        original_max: _,
-        target_min: target_min_x,
-        target_max: _,
-        scalar: scaler_x,
-    } = scaler_x;
-    let Scaler {
-        original_min: original_min_y,
-        original_max: _,
-        target_min: target_min_y,
-        target_max: _,
-        scalar: scaler_y,
-    } = scaler_y;
-
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-
-        source: wgpu::ShaderSource::Wgsl(Cow::from(format!(
-            "
-fn scale_x(n: f64) -> f64 {{
-    return (n-{original_min_x})*{scaler_x}+{target_min_x};
-}}
-
-fn scale_y(n: f64) -> f64 {{
-    return (n-{original_min_y})*{scaler_y}+{target_min_y};
-}}
-
-@group(0) @binding(0) var<storage, read_write> output: array<u32>;
-@compute
-@workgroup_size(256, 1, 1)
-fn main(
-    @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
-) {{
-    let i = global_invocation_id.x;
-    if i >= arrayLength(&output) {{
-        return;
-    }}
-    let px = i % {width};
-    let py = i / {width};
-    let y0 = scale_y(f64(py));
-    let x0 = scale_x(f64(px));
-
-    var x: f64 = 0.0;
-    var y: f64 = 0.0;
-    var x2: f64 = 0.0;
-    var y2: f64 = 0.0;
-    var iteration = 0;
-    while (x2  + y2 <= 4.0) && (iteration < {threshhold}) {{
-        y = (x + x) * y + y0;
-        x = x2 - y2 + x0;
-        x2 = x * x;
-        y2 = y * y;
-        iteration += 1;
-    }}
-    if iteration == {threshhold} {{
-        output[i] = 0;
-    }} else {{
-        output[i] = 1;
-    }}
-}}
-"
-        ))),
-    });
+    let columns: Vec<Vec<usize>> = (0..width)
+        .into_par_iter()
+        .map(|px| {
+            calculate_bifurcation_inner(px, width, height, viewport, plane, threshhold, cancel)
+        })
+        .collect();
+    for (px, rows) in columns.into_iter().enumerate() {
+        for row in rows {
+            bits.set(px, top + row, false);
+        }
+    }
+}
 
-    let buffer_size = (width * height * 4) as u64;
+fn calculate_bifurcation_singlethread(
+    width: usize,
+    top: usize,
+    height: usize,
+    viewport: &Viewport,
+    plane: &ParameterPlane,
+    threshhold: usize,
+    cancel: &CancelToken,
+    bits: &mut Bits2d,
+) {
+    for px in 0..width {
+        let rows =
+            calculate_bifurcation_inner(px, width, height, viewport, plane, threshhold, cancel);
+        for row in rows {
+            bits.set(px, top + row, false);
+        }
+    }
+}
 
-    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: None,
-        module: &shader,
-        entry_point: None,
-        compilation_options: Default::default(),
-        cache: Default::default(),
-    });
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "gpu")]
+    use super::GpuPrecision;
+    #[cfg(feature = "gpu")]
+    use super::Throughput;
+    use super::{
+        Bits2d, CalculationMethod, Calibration, Frame, Job, LatticeKey, MAX_TILES, Orbit,
+        PASS_STRIDES, ParameterMapping, ParameterPlane, PixelMapping, TileCache, Transform,
+        ViewOrbits, Viewport, auto_threshhold, calculate_bifurcation_inner,
+        calculate_cpu_multithread, calculate_cpu_singlethread, calibrate, in_pass, mirror_rows,
+        next_auto_boost, parse_arg, pixel_aspect_is_valid, render, schedule, work_through,
+    };
+    use crate::worker::CancelToken;
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use std::time::Duration;
 
-    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("output"),
-        size: buffer_size,
-        usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-        mapped_at_creation: false,
-    });
-    let temp_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("temp"),
-        size: buffer_size,
-        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-        mapped_at_creation: false,
-    });
+    /// A `PixelMapping::Linear` onto `plane`
+    fn linear(
+        transform: [[f64; 3]; 2],
+        shift: (i64, i64),
+        plane: ParameterPlane,
+    ) -> ParameterMapping {
+        ParameterMapping {
+            pixel: PixelMapping::Linear {
+                transform: Transform(transform),
+                shift,
+            },
+            plane,
+        }
+    }
 
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &pipeline.get_bind_group_layout(0),
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: output_buffer.as_entire_binding(),
-        }],
-    });
+    fn pool(threads: usize) -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn passes_compute_every_pixel_exactly_once() {
+        for py in 0..20 {
+            for px in 0..20 {
+                let passes = PASS_STRIDES
+                    .iter()
+                    .filter(|&&stride| in_pass(px, py, stride))
+                    .count();
+                assert_eq!(passes, 1, "({px}, {py})");
+            }
+        }
+    }
 
-    let mut encoder = device.create_command_encoder(&Default::default());
+    #[test]
+    fn orbits_carry_on_to_the_same_result() {
+        // Escapes after 15 iterations
+        let c = (0.4, 0.3);
+        let mut from_scratch = Orbit::default();
+        from_scratch.iterate(c.0, c.1, 500);
+        let mut continued = Orbit::default();
+        continued.iterate(c.0, c.1, 5);
+        assert_eq!(continued.drawn(5), Some(false));
+        assert_eq!(continued.drawn(500), None);
+        continued.iterate(c.0, c.1, 500);
+        assert_eq!(continued.iteration, from_scratch.iteration);
+        assert_eq!((continued.x, continued.y), (from_scratch.x, from_scratch.y));
+        assert_eq!(continued.iteration, 15);
+        assert_eq!(continued.drawn(16), Some(true));
+        assert_eq!(continued.drawn(15), Some(false));
+    }
 
-    {
-        let num_dispatchers = (width * height).div_ceil(256) as u32;
-        let mut pass = encoder.begin_compute_pass(&Default::default());
-        pass.set_pipeline(&pipeline);
-        pass.set_bind_group(0, &bind_group, &[]);
-        pass.dispatch_workgroups(num_dispatchers, 1, 1);
+    #[test]
+    fn results_answer_other_caps() {
+        let escaped = Orbit::from_result(true, 15);
+        assert_eq!(escaped.drawn(16), Some(true));
+        assert_eq!(escaped.drawn(15), Some(false));
+        let not_escaped = Orbit::from_result(false, 5);
+        assert_eq!(not_escaped.drawn(5), Some(false));
+        assert_eq!(not_escaped.drawn(500), None);
+        // Starts over for a higher cap and ends up where it would have from scratch
+        let c = (0.4, 0.3);
+        let mut from_scratch = Orbit::default();
+        from_scratch.iterate(c.0, c.1, 500);
+        let mut continued = not_escaped;
+        continued.iterate(c.0, c.1, 500);
+        assert_eq!(continued.iteration, from_scratch.iteration);
+        assert_eq!((continued.x, continued.y), (from_scratch.x, from_scratch.y));
+        // Orbits through 0 don't escape however many times they start over
+        let mut periodic = Orbit::default();
+        periodic.iterate(-1.0, 0.0, 10);
+        assert_eq!((periodic.x, periodic.y, periodic.iteration), (0.0, 0.0, 10));
+        periodic.iterate(-1.0, 0.0, 20);
+        assert_eq!(periodic.drawn(20), Some(false));
     }
 
-    encoder.copy_buffer_to_buffer(&output_buffer, 0, &temp_buffer, 0, buffer_size);
+    #[test]
+    fn nan_orbits_escape() {
+        let mut orbit = Orbit::default();
+        orbit.iterate(f64::NAN, f64::NAN, 500);
+        assert_eq!(orbit.iteration, 1);
+        assert_eq!(orbit.drawn(500), Some(true));
+        // The origin of the InverseC plane is 0/0
+        let mapping = linear(
+            [[0.01, 0.0, 0.0], [0.0, 0.01, 0.0]],
+            (-2, -2),
+            ParameterPlane::InverseC,
+        );
+        assert!(mapping.map(2, 2).0.is_nan());
+        let mut bits = Bits2d::new(5, 5);
+        let mut orbits = vec![Orbit::default(); 25];
+        for stride in PASS_STRIDES {
+            calculate_cpu_singlethread(
+                5,
+                5,
+                stride,
+                &mapping,
+                500,
+                &Bits2d::new(5, 5),
+                &mut orbits,
+                &CancelToken::default(),
+                &mut bits,
+            );
+        }
+        assert_eq!(bits.get(2, 2), Some(true));
+    }
 
-    queue.submit([encoder.finish()]);
+    #[test]
+    fn rows_mirror_their_conjugates_across_the_real_axis() {
+        let mapping = |b: f64| {
+            linear(
+                [[0.1, b, -0.5], [0.0, 0.1, 0.0]],
+                (0, -5),
+                ParameterPlane::Lambda,
+            )
+        };
+        // Rows go from -0.5 to 0.2, so the two rows above the axis are copied from below it
+        let mirrors = mirror_rows(&mapping(0.0), 4, 8);
+        assert_eq!(
+            mirrors,
+            [None, None, None, None, None, None, Some(4), Some(3)]
+        );
+        for px in 0..4 {
+            let mut orbit = Orbit::default();
+            let (x0, y0) = mapping(0.0).map(px, 6);
+            orbit.iterate(x0, y0, 100);
+            let mut mirrored = Orbit::default();
+            let (x0, y0) = mapping(0.0).map(px, 4);
+            mirrored.iterate(x0, y0, 100);
+            assert_eq!(orbit.iteration, mirrored.iteration);
+            assert_eq!((orbit.x, orbit.y), (mirrored.x, -mirrored.y));
+        }
+        // Rotated views don't have rows along the real axis
+        assert!(
+            mirror_rows(&mapping(0.01), 4, 8)
+                .iter()
+                .all(Option::is_none)
+        );
+    }
 
-    {
-        let (tx, rx) = std::sync::mpsc::channel();
-        temp_buffer.map_async(wgpu::MapMode::Read, .., move |result| {
-            tx.send(result).unwrap()
+    #[test]
+    fn queued_work_runs_on_every_thread_at_once() {
+        let pool = pool(2);
+        // Each item waits for the other one to start, which only happens if they overlap
+        let started = AtomicUsize::new(0);
+        let met = AtomicUsize::new(0);
+        let times = work_through(&pool, vec![(0, ()), (1, ())], 2, |()| {
+            started.fetch_add(1, Ordering::SeqCst);
+            let deadline = std::time::Instant::now() + Duration::from_secs(5);
+            while started.load(Ordering::SeqCst) < 2 && std::time::Instant::now() < deadline {
+                std::thread::yield_now();
+            }
+            if started.load(Ordering::SeqCst) == 2 {
+                met.fetch_add(1, Ordering::SeqCst);
+            }
         });
-        device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
-        let _ = rx.recv().unwrap();
-
-        let output_data = temp_buffer.get_mapped_range(..);
-        let mut lock = handler.lock().unwrap();
-        for x in 0..width {
-            for y in 0..height {
-                lock.set_bit(x, y, output_data[(y * width + x) * 4] != 0);
+        assert_eq!(met.load(Ordering::SeqCst), 2);
+        assert!(times.iter().all(|time| *time < Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn expensive_tiles_are_scheduled_first() {
+        // 3 by 2 tiles, the last of which is cut off
+        let (width, height) = (70, 40);
+        let mut previous = vec![0; width * height];
+        previous[35 * width + 40] = 5000;
+        previous[0] = 2000;
+        let mut skip = Bits2d::new(width, height);
+        // Skipped pixels don't cost anything
+        skip.set(0, 0, true);
+        let (order, costs) = schedule(width, height, &skip, &previous);
+        assert_eq!(order, [4, 1, 0, 3, 2, 5]);
+        assert_eq!(costs, [1023, 1024, 192, 256, 5000 + 255, 48]);
+    }
+
+    #[test]
+    fn tiles_render_the_same_as_rows() {
+        let mapping = linear(
+            [[0.03, 0.0, -0.7], [0.0, 0.04, 0.0]],
+            (-50, -20),
+            ParameterPlane::C,
+        );
+        let (width, height) = (100, 41);
+        let pool = pool(3);
+        let skip = Bits2d::new(width, height);
+        let (order, _) = schedule(width, height, &skip, &[]);
+        let cancel = CancelToken::default();
+        let mut rows = Bits2d::new(width, height + 5);
+        let mut tiles = rows.clone();
+        let mut row_orbits = vec![Orbit::default(); width * height];
+        let mut tile_orbits = row_orbits.clone();
+        for stride in PASS_STRIDES {
+            calculate_cpu_singlethread(
+                width,
+                height,
+                stride,
+                &mapping,
+                100,
+                &skip,
+                &mut row_orbits,
+                &cancel,
+                &mut rows,
+            );
+            let times = calculate_cpu_multithread(
+                width,
+                height,
+                stride,
+                &mapping,
+                100,
+                &skip,
+                &mut tile_orbits,
+                &order,
+                &pool,
+                &cancel,
+                &mut tiles,
+            );
+            assert_eq!(times.len(), 8);
+        }
+        for py in 0..height + 5 {
+            for px in 0..width {
+                assert_eq!(rows.get(px, py), tiles.get(px, py), "({px}, {py})");
+            }
+        }
+        assert!((0..width).any(|px| rows.get(px, 20) == Some(false)));
+    }
+
+    /// Renders the whole set at `threshhold` without carrying on any orbits,
+    /// returning the finished pixels and how many pixels came from `cache`
+    fn render_from_cache(
+        cache: &Arc<Mutex<TileCache<LatticeKey>>>,
+        threshhold: usize,
+    ) -> (Vec<bool>, usize) {
+        let (width, height) = (60, 40);
+        let viewport = Viewport::fit(ParameterPlane::C.home(), width, height, 1.0);
+        let job = Job {
+            bit_width: width,
+            bit_height: height,
+            mandelbrot_height: height,
+            mapping: linear(
+                viewport.transform().0,
+                viewport.lattice_shift(width, height),
+                ParameterPlane::C,
+            ),
+            viewport,
+            threshhold,
+            cache: cache.clone(),
+            orbits: Arc::new(Mutex::new(ViewOrbits::default())),
+            pool: Arc::new(pool(2)),
+            #[cfg(feature = "gpu")]
+            throughput: Arc::new(Mutex::new(Throughput::default())),
+            calibration: Arc::new(Mutex::new(None)),
+            calculation_method: CalculationMethod::CpuSingleThread,
+            settings: String::new(),
+        };
+        let finished = Mutex::new(None);
+        render(job, &CancelToken::default(), &|frame: Frame| {
+            if frame.finished {
+                *finished.lock().unwrap() = Some(frame);
             }
+        });
+        let frame = finished.into_inner().unwrap().unwrap();
+        let reused = frame.title.split("reused=").nth(1).unwrap();
+        let reused = reused.split('/').next().unwrap().parse().unwrap();
+        let pixels = (0..height)
+            .flat_map(|py| (0..width).map(move |px| (px, py)))
+            .map(|(px, py)| frame.bits.get(px, py).unwrap())
+            .collect();
+        (pixels, reused)
+    }
+
+    #[test]
+    fn cached_pixels_answer_other_caps() {
+        let fresh = |threshhold| {
+            render_from_cache(&Arc::new(Mutex::new(TileCache::new(MAX_TILES))), threshhold).0
+        };
+        let cache = Arc::new(Mutex::new(TileCache::new(MAX_TILES)));
+        assert_eq!(render_from_cache(&cache, 50), (fresh(50), 0));
+        // Every pixel is known to either have escaped or not by 20 iterations
+        assert_eq!(render_from_cache(&cache, 20), (fresh(20), 60 * 40));
+        // but only the ones that escaped are known for 500
+        let (bits, reused) = render_from_cache(&cache, 500);
+        assert_eq!(bits, fresh(500));
+        assert!(0 < reused && reused < 60 * 40, "{reused}");
+        assert_eq!(render_from_cache(&cache, 500).1, 60 * 40);
+    }
+
+    #[test]
+    fn calibration_goes_stale_when_the_cap_or_depth_moves_far_enough() {
+        let calibration = Calibration {
+            threshhold: 1000,
+            scale: 0.01,
+            method: CalculationMethod::CpuMultiThread,
+        };
+        assert!(!calibration.is_stale(1000, 0.01));
+        assert!(!calibration.is_stale(2000, 0.01 / 8.0));
+        assert!(!calibration.is_stale(500, 0.01 * 8.0));
+        assert!(calibration.is_stale(2001, 0.01));
+        assert!(calibration.is_stale(499, 0.01));
+        assert!(calibration.is_stale(1000, 0.01 / 16.0));
+        assert!(calibration.is_stale(1000, 0.01 * 16.0));
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn spacing_counts_how_the_plane_stretches_the_screen() {
+        let mapping = |plane| linear([[1e-4, 0.0, 2.9], [0.0, 1e-4, 0.0]], (0, 0), plane);
+        let c = mapping(ParameterPlane::C).spacing(1000, 10);
+        assert!((c - 1e-4).abs() < 1e-12);
+        // The pixels on the right are about 3 from the origin, where 1/p shrinks them 9 times
+        let inverse = mapping(ParameterPlane::InverseC).spacing(1000, 10);
+        assert!((inverse * 3f64.powi(2) / 1e-4 - 1.0).abs() < 0.01);
+        assert_eq!(GpuPrecision::choose(true, c, false), GpuPrecision::F32);
+        assert_eq!(
+            GpuPrecision::choose(true, inverse, false),
+            GpuPrecision::F64
+        );
+    }
+
+    #[test]
+    fn calibration_picks_one_of_the_backends() {
+        let mapping = linear(
+            [[0.01, 0.0, -0.7], [0.0, 0.01, 0.0]],
+            (-200, -100),
+            ParameterPlane::C,
+        );
+        let pool = pool(2);
+        let cpus = [
+            CalculationMethod::CpuSingleThread,
+            CalculationMethod::CpuMultiThread,
+        ];
+        let calibrate = |width, height, backends: &[CalculationMethod]| {
+            calibrate(
+                &mapping,
+                width,
+                height,
+                200,
+                &pool,
+                backends,
+                &CancelToken::default(),
+                #[cfg(feature = "gpu")]
+                GpuPrecision::F32,
+            )
+            .unwrap()
+        };
+        // Only the backends it's given are timed, so a lone one always wins
+        for method in cpus {
+            assert_eq!(calibrate(400, 200, &[method]), method);
+        }
+        assert_eq!(calibrate(400, 200, &[]), CalculationMethod::CpuSingleThread);
+        // Screens smaller than the calibration size are calibrated on all of it
+        assert_eq!(
+            calibrate(3, 2, &cpus[..1]),
+            CalculationMethod::CpuSingleThread
+        );
+        // The cropped part samples the same points as the middle of the screen
+        let cropped = mapping.pixel.crop(136, 68, 128, 64);
+        assert_eq!(cropped.map(0, 0), mapping.pixel.map(136, 68));
+        assert_eq!(cropped.map(127, 63), mapping.pixel.map(263, 131));
+    }
+
+    #[test]
+    fn calibration_is_cut_short() {
+        // Mostly inside the set, where every orbit would run to the cap
+        let mapping = linear(
+            [[0.001, 0.0, -0.2], [0.0, 0.001, 0.0]],
+            (-64, -32),
+            ParameterPlane::C,
+        );
+        let pool = pool(2);
+        let backends = [
+            CalculationMethod::CpuSingleThread,
+            CalculationMethod::CpuMultiThread,
+        ];
+        let calibrate = |threshhold, cancel: &CancelToken| {
+            calibrate(
+                &mapping,
+                128,
+                64,
+                threshhold,
+                &pool,
+                &backends,
+                cancel,
+                #[cfg(feature = "gpu")]
+                GpuPrecision::F32,
+            )
+        };
+        // Finishes in a moment however high the cap is
+        assert!(calibrate(usize::MAX, &CancelToken::default()).is_some());
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        assert_eq!(calibrate(200, &cancel), None);
+    }
+
+    /// The next five methods after `CpuSingleThread`
+    fn cycle(
+        #[cfg(feature = "gpu")] gpu_available: bool,
+        #[cfg(feature = "gpu")] gpu_f64: bool,
+    ) -> Vec<CalculationMethod> {
+        let mut method = CalculationMethod::CpuSingleThread;
+        let mut methods = Vec::new();
+        for _ in 0..5 {
+            method.cycle(
+                #[cfg(feature = "gpu")]
+                gpu_available,
+                #[cfg(feature = "gpu")]
+                gpu_f64,
+            );
+            methods.push(method);
+        }
+        methods
+    }
+
+    #[test]
+    fn cycling_skips_the_gpu_methods_without_a_gpu() {
+        assert_eq!(
+            cycle(
+                #[cfg(feature = "gpu")]
+                false,
+                #[cfg(feature = "gpu")]
+                false,
+            ),
+            [
+                CalculationMethod::CpuMultiThread,
+                CalculationMethod::Auto,
+                CalculationMethod::CpuSingleThread,
+                CalculationMethod::CpuMultiThread,
+                CalculationMethod::Auto,
+            ]
+        );
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn cycling_goes_through_the_gpu_methods_with_a_gpu() {
+        assert_eq!(
+            cycle(true, true),
+            [
+                CalculationMethod::CpuMultiThread,
+                CalculationMethod::Gpu,
+                CalculationMethod::Hybrid,
+                CalculationMethod::Auto,
+                CalculationMethod::CpuSingleThread,
+            ]
+        );
+        // Hybrid needs f64 to match the CPU
+        assert_eq!(
+            cycle(true, false),
+            [
+                CalculationMethod::CpuMultiThread,
+                CalculationMethod::Gpu,
+                CalculationMethod::Auto,
+                CalculationMethod::CpuSingleThread,
+                CalculationMethod::CpuMultiThread,
+            ]
+        );
+    }
+
+    #[test]
+    fn auto_threshhold_grows_with_depth_and_boost() {
+        assert_eq!(auto_threshhold(0.01, 0.01, 1.0), 200);
+        assert_eq!(auto_threshhold(0.01, 0.02, 1.0), 200);
+        assert_eq!(auto_threshhold(0.01, 0.01 / 1024.0, 1.0), 1200);
+        assert_eq!(auto_threshhold(0.01, 0.01 / 1024.0, 1.5), 1800);
+        assert_eq!(next_auto_boost(1.0, 0.1), 1.5);
+        assert_eq!(next_auto_boost(1.5, 0.01), 1.5);
+        assert_eq!(next_auto_boost(1.5, 0.0), 1.2);
+        assert_eq!(next_auto_boost(1.1, 0.0), 1.0);
+    }
+
+    #[test]
+    fn parameter_planes_map_landmarks_onto_c() {
+        assert_eq!(ParameterPlane::C.to_c(-0.75, 0.1), (-0.75, 0.1));
+        // Cusp of the main cardioid and tip of the antenna
+        assert_eq!(ParameterPlane::InverseC.to_c(4.0, 0.0), (0.25, -0.0));
+        assert_eq!(ParameterPlane::InverseC.to_c(-0.5, 0.0), (-2.0, -0.0));
+        assert_eq!(ParameterPlane::Lambda.to_c(1.0, 0.0), (0.25, 0.0));
+        assert_eq!(ParameterPlane::Lambda.to_c(4.0, 0.0), (-2.0, 0.0));
+        assert_eq!(ParameterPlane::Lambda.to_c(-2.0, 0.0), (-2.0, 0.0));
+    }
+
+    #[test]
+    fn bifurcation_plots_the_attractors() {
+        // Centered on 0, with 0.1 between columns
+        let viewport = Viewport::fit((-1.0, 1.0, -1.0, 1.0), 20, 20, 1.0);
+        let rows = |px| {
+            let mut rows = calculate_bifurcation_inner(
+                px,
+                20,
+                40,
+                &viewport,
+                &ParameterPlane::C,
+                100,
+                &CancelToken::default(),
+            );
+            rows.sort();
+            rows.dedup();
+            rows
+        };
+        // c = -1 settles into the cycle 0, -1, 0, ...
+        assert_eq!(rows(0), [20, 30]);
+        // c = 0.3 is past the cusp at 1/4, so it escapes
+        assert_eq!(rows(13), []);
+    }
+
+    #[test]
+    fn log_polar_starts_at_the_corners_and_zooms_in_row_by_row() {
+        let (width, height) = (64, 20);
+        let mut viewport = Viewport::fit((-2.0, 0.5, -1.0, 1.0), width, height, 2.0);
+        viewport.rotation = 0.5;
+        let PixelMapping::LogPolar {
+            center_x,
+            center_y,
+            radii,
+            directions,
+        } = PixelMapping::log_polar(width, height, &viewport)
+        else {
+            panic!("not log polar");
+        };
+        assert_eq!((center_x, center_y), viewport.center());
+        // Where the linear view's top left pixel is
+        let (shift_x, shift_y) = viewport.lattice_shift(width, height);
+        let (corner_x, corner_y) = viewport.transform().apply(shift_x as f64, shift_y as f64);
+        let corner = (corner_x - center_x).hypot(corner_y - center_y);
+        assert!((radii[0] / corner - 1.0).abs() < 1e-12);
+        let step = (-std::f64::consts::TAU / width as f64 * viewport.aspect).exp();
+        for pair in radii.windows(2) {
+            assert!((pair[1] / pair[0] / step - 1.0).abs() < 1e-12);
+        }
+        let (sin, cos) = viewport.rotation.sin_cos();
+        assert_eq!(directions[0], (cos, sin));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        let threads = |args: &[&str]| {
+            parse_arg(
+                args.iter().map(|arg| arg.to_string()),
+                "--threads",
+                "a number",
+                |_: &usize| true,
+            )
+        };
+        assert_eq!(threads(&["app", "--threads", "3"]), Ok(Some(3)));
+        assert_eq!(threads(&["app"]), Ok(None));
+        assert_eq!(
+            threads(&["app", "--threads"]),
+            Err("--threads needs a value".to_string())
+        );
+        assert_eq!(
+            threads(&["app", "--threads", "-1"]),
+            Err("--threads should be a number, not \"-1\"".to_string())
+        );
+        let pixel_aspect = |value: &str| {
+            let args = ["app", "--pixel-aspect", value].map(str::to_string);
+            parse_arg(
+                args.into_iter(),
+                "--pixel-aspect",
+                "a positive number",
+                pixel_aspect_is_valid,
+            )
+        };
+        assert_eq!(pixel_aspect("1.5"), Ok(Some(1.5)));
+        for value in ["tall", "0", "-1", "nan", "inf", "-inf"] {
+            assert_eq!(
+                pixel_aspect(value),
+                Err(format!(
+                    "--pixel-aspect should be a positive number, not {value:?}"
+                ))
+            );
         }
     }
-    temp_buffer.unmap();
 }
@@ -1,41 +1,57 @@
 use crate::bits2d::Bits2d;
 use crossterm::{ExecutableCommand, QueueableCommand};
 use std::io::{Result, Stdout, Write};
+use std::time::Duration;
 
-pub fn run<T>(stdout: Stdout, memory: T, on_event: impl Fn(&mut Handler<T>, Option<crossterm::event::KeyEvent>) -> bool) -> Result<()> {
+/// How long to wait for an event before calling `on_tick` anyway
+const TICK: Duration = Duration::from_millis(10);
+
+/// `on_tick` gets called every `TICK` while there aren't any events,
+/// so work finishing in the background can be shown without waiting for input.
+/// Events that queue up are all passed to `on_event` before the next `on_tick`,
+/// so anything expensive should be left for `on_tick` to do once for all of them
+pub fn run<T>(stdout: Stdout, memory: T, on_event: impl Fn(&mut Handler<T>, Option<crossterm::event::Event>) -> bool, on_tick: impl Fn(&mut Handler<T>)) -> Result<()> {
     let mut handler = Handler::new(stdout, memory)?;
     on_event(&mut handler, None);
     handler.render_bits()?;
     loop {
-        match crossterm::event::read()? {
+        on_tick(&mut handler);
+        if !crossterm::event::poll(TICK)? {
+            continue;
+        }
+        loop {
+            match crossterm::event::read()? {
                 crossterm::event::Event::FocusGained => {},
                 crossterm::event::Event::FocusLost => {},
-                crossterm::event::Event::Key(key_event) => {
-                    if on_event(&mut handler, Some(key_event)) {
-                        break;
+                event @ (crossterm::event::Event::Key(_) | crossterm::event::Event::Mouse(_)) => {
+                    if on_event(&mut handler, Some(event)) {
+                        return Ok(());
                     }
                 },
-                crossterm::event::Event::Mouse(_mouse_event) => {},
                 crossterm::event::Event::Paste(_) => {},
-                crossterm::event::Event::Resize(new_term_width, new_term_height) => {
-                    let (new_bit_width, new_bit_height) = sextant_size((new_term_width, new_term_height));
-                    handler.term_width = new_term_width;
-                    handler.term_height = new_term_height;
-                    handler.bits.resize(new_bit_width, new_bit_height, false);
-                    if on_event(&mut handler, None) {
-                        break
+                event @ crossterm::event::Event::Resize(new_term_width, new_term_height) => {
+                    handler.resize(new_term_width, new_term_height);
+                    if on_event(&mut handler, Some(event)) {
+                        return Ok(());
                     }
-                    handler.render_bits()?;
                 },
             }
+            if !crossterm::event::poll(Duration::ZERO)? {
+                break;
+            }
+        }
     }
-    Ok(())
 }
 
 fn sextant_size(term_size: (u16, u16)) -> (usize, usize) {
     (term_size.0 as usize * 2, term_size.1 as usize * 3)
 }
 
+/// The bit in the middle of the terminal cell
+pub fn cell_to_bit(column: u16, row: u16) -> (usize, usize) {
+    (column as usize * 2 + 1, row as usize * 3 + 1)
+}
+
 pub struct Handler<T>
 {
     stdout: Stdout,
@@ -48,6 +64,7 @@ pub struct Handler<T>
 impl<T> Handler<T> {
     fn new(mut stdout: Stdout, memory: T) -> Result<Self> {
         stdout.execute(crossterm::terminal::EnterAlternateScreen)?;
+        stdout.execute(crossterm::event::EnableMouseCapture)?;
         crossterm::terminal::enable_raw_mode()?;
         let (term_width, term_height) = crossterm::terminal::size()?;
         let (sextant_width, sextant_height) = sextant_size((term_width, term_height));
@@ -60,6 +77,13 @@ impl<T> Handler<T> {
         })
     }
 
+    fn resize(&mut self, term_width: u16, term_height: u16) {
+        let (bit_width, bit_height) = sextant_size((term_width, term_height));
+        self.term_width = term_width;
+        self.term_height = term_height;
+        self.bits.resize(bit_width, bit_height, false);
+    }
+
     pub fn render_bits(&mut self) -> Result<()> {
         self.stdout.queue(crossterm::terminal::Clear(
             crossterm::terminal::ClearType::All,
@@ -86,23 +110,23 @@ impl<T> Handler<T> {
     pub fn get_bit(&self, x: usize, y: usize) -> Option<bool> {
         self.bits.get(x, y)
     }
-    pub fn set_bit(&mut self, x: usize, y: usize, b: bool) {
-        self.bits.set(x, y, b);
-    }
-    pub fn set_bits_all_zero(&mut self) {
-        self.bits.set_all_zero();
-    }
-    pub fn set_bits_all_one(&mut self) {
-        self.bits.set_all_one();
-    }
     pub fn bit_width(&self) -> usize {
         self.bits.width()
     }
     pub fn bit_height(&self) -> usize {
         self.bits.height()
     }
-    pub fn bit_area(&self) -> usize {
-        self.bits.area()
+    /// How many times taller than wide a bit is on screen,
+    /// if the terminal reports its size in pixels
+    pub fn bit_aspect(&self) -> Option<f64> {
+        let size = crossterm::terminal::window_size().ok()?;
+        if size.width == 0 || size.height == 0 || size.columns == 0 || size.rows == 0 {
+            return None;
+        }
+        let cell_width = size.width as f64 / size.columns as f64;
+        let cell_height = size.height as f64 / size.rows as f64;
+        let (bit_width, bit_height) = sextant_size((1, 1));
+        Some((cell_height / bit_height as f64) / (cell_width / bit_width as f64))
     }
     pub fn set_title(&mut self, title: impl std::fmt::Display) -> Result<()> {
         self.stdout.execute(crossterm::terminal::SetTitle(title)).map(|_|())
@@ -112,6 +136,7 @@ impl<T> Handler<T> {
 impl<T> Drop for Handler<T> {
     fn drop(&mut self) {
         let _ = crossterm::terminal::disable_raw_mode();
+        let _ = self.stdout.execute(crossterm::event::DisableMouseCapture);
         let _ = self.stdout
             .execute(crossterm::terminal::LeaveAlternateScreen);
     }
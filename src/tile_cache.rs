@@ -0,0 +1,388 @@
+use crate::bits2d::Bits2d;
+use std::{collections::HashMap, hash::Hash};
+
+/// Width and height of a tile, in lattice points
+pub const TILE_SIZE: usize = 64;
+
+/// How many levels up and down `TileCache::seed` looks for coinciding points
+const LEVEL_REACH: i32 = 3;
+
+/// Which tile of which level of which lattice
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct TileKey<L> {
+    lattice: L,
+    level: i32,
+    x: i64,
+    y: i64,
+}
+
+#[derive(Debug)]
+struct Tile {
+    values: Bits2d,
+    /// Which of `values` have been computed
+    known: Bits2d,
+    /// How many iterations each point took, row by row
+    iterations: Vec<u32>,
+    /// When it was last seeded from or stored into, for eviction
+    last_used: u64,
+}
+
+/// Results of previous frames, split into square tiles of lattice points
+/// so that anything computed on one level of a lattice can be found again whenever it comes back
+/// on screen, no matter how the view got there.
+/// `L` is everything the results depend on besides the level and the lattice coordinates.
+/// Holds at most `capacity` tiles, evicting the least recently used ones
+#[derive(Debug)]
+pub struct TileCache<L> {
+    tiles: HashMap<TileKey<L>, Tile>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl<L: Clone + Eq + Hash> TileCache<L> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tiles: HashMap::new(),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Calls `f` with every tile overlapping a `width` by `height` screen
+    /// whose pixel `(0, 0)` is at lattice coordinates `shift`,
+    /// along with the pixel coordinates of the tile's top left corner
+    fn for_each_tile(
+        width: usize,
+        height: usize,
+        shift: (i64, i64),
+        mut f: impl FnMut(i64, i64, i64, i64),
+    ) {
+        let size = TILE_SIZE as i64;
+        let tiles = |shift: i64, len: usize| {
+            shift.div_euclid(size)..=(shift + len as i64 - 1).div_euclid(size)
+        };
+        if width == 0 || height == 0 {
+            return;
+        }
+        for y in tiles(shift.1, height) {
+            for x in tiles(shift.0, width) {
+                f(x, y, x * size - shift.0, y * size - shift.1);
+            }
+        }
+    }
+
+    /// Copies every cached pixel of a screen the size of `known` into `bits`,
+    /// marking them in `known`, and how many iterations they took into `iterations`.
+    /// `shift` is the lattice coordinates of pixel `(0, 0)`.
+    /// Points of other levels within `LEVEL_REACH` that coincide with the screen's are used too,
+    /// so after zooming in 2x a quarter of the screen is already there.
+    /// Returns how many there were
+    pub fn seed(
+        &mut self,
+        lattice: &L,
+        level: i32,
+        shift: (i64, i64),
+        bits: &mut Bits2d,
+        known: &mut Bits2d,
+        iterations: &mut [usize],
+    ) -> usize {
+        self.clock += 1;
+        let mut reused =
+            self.seed_from_level(lattice, level, level, shift, bits, known, iterations);
+        for steps in 1..=LEVEL_REACH {
+            for other in [level - steps, level + steps] {
+                reused +=
+                    self.seed_from_level(lattice, level, other, shift, bits, known, iterations);
+            }
+        }
+        reused
+    }
+
+    /// `seed` from the points of `other` level that coincide with points of `level`.
+    /// Point `(x, y)` of a level is point `(2x, 2y)` of the level below it
+    fn seed_from_level(
+        &mut self,
+        lattice: &L,
+        level: i32,
+        other: i32,
+        shift: (i64, i64),
+        bits: &mut Bits2d,
+        known: &mut Bits2d,
+        iterations: &mut [usize],
+    ) -> usize {
+        let (width, height) = (known.width(), known.height());
+        if width == 0 || height == 0 {
+            return 0;
+        }
+        let steps = level - other;
+        // Lattice coordinates on `other` of the nearest point at or after `l` on `level`,
+        // and back, if they coincide
+        let to_other = |l: i64| {
+            if steps >= 0 {
+                l.div_euclid(1 << steps) + i64::from(l.rem_euclid(1 << steps) != 0)
+            } else {
+                l << -steps
+            }
+        };
+        let from_other = |o: i64| {
+            if steps >= 0 {
+                Some(o << steps)
+            } else {
+                (o.rem_euclid(1 << -steps) == 0).then(|| o >> -steps)
+            }
+        };
+        let (left, top) = (to_other(shift.0), to_other(shift.1));
+        let (right, bottom) = (
+            to_other(shift.0 + width as i64),
+            to_other(shift.1 + height as i64),
+        );
+        if left >= right || top >= bottom {
+            return 0;
+        }
+        let mut reused = 0;
+        let size = TILE_SIZE as i64;
+        for y in top.div_euclid(size)..=(bottom - 1).div_euclid(size) {
+            for x in left.div_euclid(size)..=(right - 1).div_euclid(size) {
+                let key = TileKey {
+                    lattice: lattice.clone(),
+                    level: other,
+                    x,
+                    y,
+                };
+                let Some(tile) = self.tiles.get_mut(&key) else {
+                    continue;
+                };
+                tile.last_used = self.clock;
+                for ty in 0..TILE_SIZE {
+                    let oy = y * size + ty as i64;
+                    if oy < top || oy >= bottom {
+                        continue;
+                    }
+                    let Some(ly) = from_other(oy) else {
+                        continue;
+                    };
+                    let py = (ly - shift.1) as usize;
+                    for tx in 0..TILE_SIZE {
+                        let ox = x * size + tx as i64;
+                        if ox < left || ox >= right {
+                            continue;
+                        }
+                        let Some(lx) = from_other(ox) else {
+                            continue;
+                        };
+                        let px = (lx - shift.0) as usize;
+                        if tile.known.get(tx, ty) == Some(true) && known.get(px, py) == Some(false)
+                        {
+                            bits.set(px, py, tile.values.get(tx, ty).unwrap());
+                            known.set(px, py, true);
+                            iterations[py * width + px] =
+                                tile.iterations[ty * TILE_SIZE + tx] as usize;
+                            reused += 1;
+                        }
+                    }
+                }
+            }
+        }
+        reused
+    }
+
+    /// Stores the top `height` rows of `bits`, whose pixel `(0, 0)` is at lattice coordinates `shift`,
+    /// along with how many iterations each pixel took from `iterations`,
+    /// then evicts tiles until there are at most `capacity`
+    pub fn store(
+        &mut self,
+        lattice: &L,
+        level: i32,
+        shift: (i64, i64),
+        bits: &Bits2d,
+        iterations: &[usize],
+        height: usize,
+    ) {
+        self.clock += 1;
+        let width = bits.width();
+        let height = height.min(bits.height());
+        Self::for_each_tile(width, height, shift, |x, y, left, top| {
+            let key = TileKey {
+                lattice: lattice.clone(),
+                level,
+                x,
+                y,
+            };
+            let tile = self.tiles.entry(key).or_insert_with(|| Tile {
+                values: Bits2d::new(TILE_SIZE, TILE_SIZE),
+                known: Bits2d::new(TILE_SIZE, TILE_SIZE),
+                iterations: vec![0; TILE_SIZE * TILE_SIZE],
+                last_used: 0,
+            });
+            tile.last_used = self.clock;
+            for ty in 0..TILE_SIZE {
+                let Ok(py) = usize::try_from(top + ty as i64) else {
+                    continue;
+                };
+                if py >= height {
+                    break;
+                }
+                for tx in 0..TILE_SIZE {
+                    let Ok(px) = usize::try_from(left + tx as i64) else {
+                        continue;
+                    };
+                    if px >= width {
+                        break;
+                    }
+                    tile.values.set(tx, ty, bits.get(px, py).unwrap());
+                    tile.known.set(tx, ty, true);
+                    tile.iterations[ty * TILE_SIZE + tx] = iterations[py * width + px] as u32;
+                }
+            }
+        });
+        while self.tiles.len() > self.capacity {
+            let oldest = self
+                .tiles
+                .iter()
+                .min_by_key(|(_, tile)| tile.last_used)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            self.tiles.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TILE_SIZE, TileCache};
+    use crate::bits2d::Bits2d;
+
+    fn checkerboard(width: usize, height: usize, shift: (i64, i64)) -> Bits2d {
+        let mut bits = Bits2d::new(width, height);
+        for py in 0..height {
+            for px in 0..width {
+                let (lx, ly) = (px as i64 + shift.0, py as i64 + shift.1);
+                bits.set(px, py, (lx + ly).rem_euclid(2) == 0);
+            }
+        }
+        bits
+    }
+
+    /// A different count for each of a few neighboring lattice points
+    fn counts(width: usize, height: usize, shift: (i64, i64)) -> Vec<usize> {
+        (0..width * height)
+            .map(|i| {
+                let (lx, ly) = ((i % width) as i64 + shift.0, (i / width) as i64 + shift.1);
+                (lx + 10 * ly).rem_euclid(100) as usize
+            })
+            .collect()
+    }
+
+    #[test]
+    fn panned_screens_get_back_what_was_stored() {
+        let mut cache = TileCache::new(100);
+        let shift = (-10, 20);
+        cache.store(
+            &(),
+            3,
+            shift,
+            &checkerboard(100, 50, shift),
+            &counts(100, 50, shift),
+            40,
+        );
+        let mut bits = Bits2d::new(100, 50);
+        let mut known = Bits2d::new(100, 50);
+        let mut iterations = vec![0; 100 * 50];
+        // Overlaps 80 columns and 33 rows of the stored part
+        assert_eq!(
+            cache.seed(&(), 3, (10, 27), &mut bits, &mut known, &mut iterations),
+            80 * 33
+        );
+        let expected = checkerboard(100, 50, (10, 27));
+        let expected_iterations = counts(100, 50, (10, 27));
+        for py in 0..50 {
+            for px in 0..100 {
+                let stored = px < 80 && py < 33;
+                assert_eq!(known.get(px, py), Some(stored), "({px}, {py})");
+                if stored {
+                    assert_eq!(bits.get(px, py), expected.get(px, py), "({px}, {py})");
+                    let i = py * 100 + px;
+                    assert_eq!(iterations[i], expected_iterations[i], "({px}, {py})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lattices_are_kept_apart() {
+        let mut cache = TileCache::new(100);
+        cache.store(&'a', 0, (0, 0), &Bits2d::new(10, 10), &[0; 100], 10);
+        let mut bits = Bits2d::new(10, 10);
+        let mut known = Bits2d::new(10, 10);
+        let mut iterations = [0; 100];
+        assert_eq!(
+            cache.seed(&'b', 0, (0, 0), &mut bits, &mut known, &mut iterations),
+            0
+        );
+        assert_eq!(
+            cache.seed(&'a', 0, (0, 0), &mut bits, &mut known, &mut iterations),
+            100
+        );
+    }
+
+    #[test]
+    fn levels_share_the_points_they_have_in_common() {
+        let mut cache = TileCache::new(100);
+        // Even columns are set, on level 0
+        cache.store(&(), 0, (-5, 0), &checkerboard(10, 1, (-5, 0)), &[0; 10], 1);
+        let mut bits = Bits2d::new(20, 1);
+        let mut known = Bits2d::new(20, 1);
+        let mut iterations = [0; 20];
+        // Level 1 is zoomed in 2x, so every other point of it is on level 0
+        assert_eq!(
+            cache.seed(&(), 1, (-10, 0), &mut bits, &mut known, &mut iterations),
+            10
+        );
+        for px in 0..20 {
+            assert_eq!(known.get(px, 0), Some(px % 2 == 0), "({px}, 0)");
+            if px % 2 == 0 {
+                let level_0 = (px as i64 - 10) / 2;
+                assert_eq!(bits.get(px, 0), Some(level_0 % 2 == 0), "({px}, 0)");
+            }
+        }
+        // And level -1 is zoomed out 2x
+        let mut bits = Bits2d::new(10, 1);
+        let mut known = Bits2d::new(10, 1);
+        assert_eq!(
+            cache.seed(&(), -1, (-5, 0), &mut bits, &mut known, &mut iterations),
+            5
+        );
+        for px in 0..10 {
+            assert_eq!(known.get(px, 0), Some((3..8).contains(&px)), "({px}, 0)");
+        }
+    }
+
+    #[test]
+    fn least_recently_used_tiles_are_evicted() {
+        let mut cache = TileCache::new(2);
+        let tile = Bits2d::new(TILE_SIZE, TILE_SIZE);
+        let mut iterations = vec![0; TILE_SIZE * TILE_SIZE];
+        let size = TILE_SIZE as i64;
+        cache.store(&(), 0, (0, 0), &tile, &iterations, TILE_SIZE);
+        cache.store(&(), 0, (size, 0), &tile, &iterations, TILE_SIZE);
+        let mut bits = tile.clone();
+        let mut known = tile.clone();
+        cache.seed(&(), 0, (0, 0), &mut bits, &mut known, &mut iterations);
+        cache.store(&(), 0, (2 * size, 0), &tile, &iterations, TILE_SIZE);
+        assert_eq!(cache.len(), 2);
+        let mut known = Bits2d::new(TILE_SIZE, TILE_SIZE);
+        assert_eq!(
+            cache.seed(&(), 0, (size, 0), &mut bits, &mut known, &mut iterations),
+            0,
+            "second tile should have been evicted"
+        );
+        assert_eq!(
+            cache.seed(&(), 0, (0, 0), &mut bits, &mut known, &mut iterations),
+            TILE_SIZE * TILE_SIZE
+        );
+    }
+}
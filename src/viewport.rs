@@ -0,0 +1,326 @@
+/// Affine map from lattice coordinates to the plane,
+/// `x = self.0[0][0] * lx + self.0[0][1] * ly + self.0[0][2]`
+/// and `y = self.0[1][0] * lx + self.0[1][1] * ly + self.0[1][2]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform(pub [[f64; 3]; 2]);
+
+impl Transform {
+    pub fn apply(&self, lx: f64, ly: f64) -> (f64, f64) {
+        let [[a, b, c], [d, e, f]] = self.0;
+        (a * lx + b * ly + c, d * lx + e * ly + f)
+    }
+}
+
+/// What part of the plane is on screen.
+///
+/// Pixels sample the points of a lattice anchored at `origin`,
+/// and the center of the view is always at pixel `(width / 2, height / 2)`.
+/// Panning by whole pixels and resizing only change which lattice points are on screen,
+/// so a point keeps its lattice coordinates (and the exact f64s it samples).
+/// Zooming by a power of two moves to another level of the same lattice,
+/// where every other point is a point of the level above.
+/// Any other zoom, rotation or change of aspect starts a new lattice
+#[derive(Debug, Clone, PartialEq)]
+pub struct Viewport {
+    pub origin_x: f64,
+    pub origin_y: f64,
+    /// Lattice coordinates of the center of the view, at `level`
+    pub offset_x: i64,
+    pub offset_y: i64,
+    /// Distance on the plane between neighboring pixels at level 0
+    pub base_scale: f64,
+    /// Each level halves the distance between neighboring pixels
+    pub level: i32,
+    /// Counterclockwise, in radians
+    pub rotation: f64,
+    /// How many times taller than wide a pixel is on screen
+    pub aspect: f64,
+}
+
+impl Viewport {
+    /// The smallest unrotated view centered on the given bounds that fits all of them on screen
+    pub fn fit(
+        (x_min, x_max, y_min, y_max): (f64, f64, f64, f64),
+        width: usize,
+        height: usize,
+        aspect: f64,
+    ) -> Viewport {
+        Viewport {
+            origin_x: (x_min + x_max) / 2.0,
+            origin_y: (y_min + y_max) / 2.0,
+            offset_x: 0,
+            offset_y: 0,
+            base_scale: ((x_max - x_min) / width as f64)
+                .max((y_max - y_min) / (height as f64 * aspect)),
+            level: 0,
+            rotation: 0.0,
+            aspect,
+        }
+    }
+
+    /// Distance on the plane between neighboring pixels
+    pub fn scale(&self) -> f64 {
+        self.base_scale * 2f64.powi(-self.level)
+    }
+
+    /// Maps lattice coordinates at `level` to the plane.
+    /// Scaling by powers of two is exact, so the transforms of all levels agree exactly
+    /// on the points they share
+    pub fn transform(&self) -> Transform {
+        let (sin, cos) = self.rotation.sin_cos();
+        let scale = self.scale();
+        let a = scale * cos;
+        let b = -scale * sin * self.aspect;
+        let d = scale * sin;
+        let e = scale * cos * self.aspect;
+        Transform([[a, b, self.origin_x], [d, e, self.origin_y]])
+    }
+
+    /// The transform of level 0, which is the same for every level of the lattice
+    pub fn lattice(&self) -> Transform {
+        Viewport {
+            level: 0,
+            ..self.clone()
+        }
+        .transform()
+    }
+
+    /// Lattice coordinates of pixel `(0, 0)` on a screen of this size,
+    /// pixel `(px, py)` samples lattice point `(px + shift.0, py + shift.1)`
+    pub fn lattice_shift(&self, width: usize, height: usize) -> (i64, i64) {
+        (
+            self.offset_x - (width / 2) as i64,
+            self.offset_y - (height / 2) as i64,
+        )
+    }
+
+    pub fn center(&self) -> (f64, f64) {
+        self.transform()
+            .apply(self.offset_x as f64, self.offset_y as f64)
+    }
+
+    /// Starts a new lattice anchored at the center of the view, for when the lattice changes anyway
+    fn reanchor(&mut self) {
+        (self.origin_x, self.origin_y) = self.center();
+        self.offset_x = 0;
+        self.offset_y = 0;
+        self.base_scale = self.scale();
+        self.level = 0;
+    }
+
+    /// Moves the view by a number of pixels along the screen's axes
+    pub fn pan(&mut self, dx: i64, dy: i64) {
+        self.offset_x += dx;
+        self.offset_y += dy;
+    }
+
+    /// Multiplies the size of what's on screen by `factor`, keeping the center where it is.
+    /// Zooming out by a power of two moves the center to the nearest point of the coarser level,
+    /// which is less than a pixel away
+    pub fn zoom(&mut self, factor: f64) {
+        match levels(factor) {
+            Some(levels) if levels >= 0 => {
+                self.offset_x <<= levels;
+                self.offset_y <<= levels;
+                self.level += levels;
+            }
+            Some(levels) => {
+                let d = 1 << -levels;
+                self.offset_x = (self.offset_x + d / 2).div_euclid(d);
+                self.offset_y = (self.offset_y + d / 2).div_euclid(d);
+                self.level += levels;
+            }
+            None => {
+                self.reanchor();
+                self.base_scale *= factor;
+            }
+        }
+    }
+
+    /// Multiplies the size of what's on screen by `factor`,
+    /// keeping the point `dx`, `dy` pixels from the center where it is
+    /// (to the nearest pixel when zooming by a power of two)
+    pub fn zoom_about(&mut self, factor: f64, dx: f64, dy: f64) {
+        if levels(factor).is_some() {
+            self.pan(
+                (dx * (1.0 - factor)).round() as i64,
+                (dy * (1.0 - factor)).round() as i64,
+            );
+        } else {
+            self.reanchor();
+            (self.origin_x, self.origin_y) = self
+                .transform()
+                .apply(dx * (1.0 - factor), dy * (1.0 - factor));
+        }
+        self.zoom(factor);
+    }
+
+    pub fn rotate(&mut self, angle: f64) {
+        self.reanchor();
+        self.rotation = (self.rotation + angle).rem_euclid(std::f64::consts::TAU);
+    }
+
+    pub fn set_aspect(&mut self, aspect: f64) {
+        if aspect != self.aspect {
+            self.reanchor();
+            self.aspect = aspect;
+        }
+    }
+}
+
+/// How many levels deeper zooming by `factor` goes, if it's a power of two
+fn levels(factor: f64) -> Option<i32> {
+    let levels = -factor.log2();
+    (levels.fract() == 0.0 && levels.abs() < 32.0).then_some(levels as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Viewport;
+
+    fn sample(viewport: &Viewport, width: usize, height: usize, px: i64, py: i64) -> (f64, f64) {
+        let (shift_x, shift_y) = viewport.lattice_shift(width, height);
+        viewport
+            .transform()
+            .apply((px + shift_x) as f64, (py + shift_y) as f64)
+    }
+
+    #[test]
+    fn center_is_in_the_middle_of_the_screen() {
+        let mut viewport = Viewport {
+            origin_x: -0.75,
+            origin_y: 0.25,
+            offset_x: 0,
+            offset_y: 0,
+            base_scale: 0.5,
+            level: 0,
+            rotation: 1.0,
+            aspect: 1.5,
+        };
+        assert_eq!(sample(&viewport, 40, 30, 20, 15), (-0.75, 0.25));
+        viewport.pan(3, -2);
+        assert_eq!(sample(&viewport, 40, 30, 20, 15), viewport.center());
+    }
+
+    #[test]
+    fn resizing_keeps_the_samples_around_the_center() {
+        let viewport = Viewport {
+            origin_x: -0.1,
+            origin_y: 0.9,
+            offset_x: 5,
+            offset_y: -3,
+            base_scale: 0.001,
+            level: 0,
+            rotation: 0.0,
+            aspect: 1.0,
+        };
+        assert_eq!(
+            sample(&viewport, 80, 60, 40, 30),
+            sample(&viewport, 121, 31, 60, 15)
+        );
+        assert_eq!(
+            sample(&viewport, 80, 60, 41, 29),
+            sample(&viewport, 121, 31, 61, 14)
+        );
+    }
+
+    #[test]
+    fn panning_back_lands_on_the_same_samples() {
+        let mut viewport = Viewport {
+            origin_x: -0.5,
+            origin_y: 0.1,
+            offset_x: 0,
+            offset_y: 0,
+            base_scale: 0.013,
+            level: 0,
+            rotation: 0.7,
+            aspect: 1.3,
+        };
+        let before = sample(&viewport, 80, 60, 5, 7);
+        for _ in 0..1000 {
+            viewport.pan(7, -3);
+        }
+        for _ in 0..1000 {
+            viewport.pan(-7, 3);
+        }
+        assert_eq!(sample(&viewport, 80, 60, 5, 7), before);
+    }
+
+    #[test]
+    fn zooming_by_powers_of_two_stays_on_the_lattice() {
+        let mut viewport = Viewport {
+            origin_x: -0.5,
+            origin_y: 0.1,
+            offset_x: 3,
+            offset_y: -2,
+            base_scale: 0.013,
+            level: 0,
+            rotation: 0.7,
+            aspect: 1.3,
+        };
+        let before = viewport.clone();
+        viewport.zoom(0.5);
+        assert_eq!(viewport.center(), before.center());
+        assert_eq!(viewport.lattice(), before.lattice());
+        // Every other point of the finer level is a point of the coarser one
+        let fine = viewport.transform().apply(10.0, -6.0);
+        let coarse = before.transform().apply(5.0, -3.0);
+        assert_eq!(fine, coarse);
+        viewport.zoom(0.25);
+        viewport.zoom(8.0);
+        assert_eq!(viewport, before);
+        viewport.zoom(1.25);
+        assert_eq!(viewport.level, 0);
+        assert_ne!(viewport.lattice(), before.lattice());
+    }
+
+    #[test]
+    fn zoom_about_keeps_the_pivot_in_place() {
+        let mut viewport = Viewport {
+            origin_x: -0.5,
+            origin_y: 0.0,
+            offset_x: 4,
+            offset_y: 9,
+            base_scale: 0.01,
+            level: 0,
+            rotation: 0.3,
+            aspect: 1.25,
+        };
+        let before = sample(&viewport, 100, 60, 80, 10);
+        viewport.zoom_about(0.5, 30.0, -20.0);
+        let after = sample(&viewport, 100, 60, 80, 10);
+        assert!((before.0 - after.0).abs() < 1e-12);
+        assert!((before.1 - after.1).abs() < 1e-12);
+        assert_eq!(viewport.scale(), 0.005);
+    }
+
+    #[test]
+    fn tall_pixels_cover_more_of_the_plane_vertically() {
+        let viewport = Viewport::fit((-2.0, 2.0, -1.0, 1.0), 40, 10, 2.0);
+        assert_eq!(viewport.scale(), 0.1);
+        assert_eq!(sample(&viewport, 40, 10, 0, 0), (-2.0, -1.0));
+        assert_eq!(sample(&viewport, 40, 10, 40, 10), (2.0, 1.0));
+    }
+
+    #[test]
+    fn quarter_turn_maps_screen_x_onto_plane_y() {
+        let mut viewport = Viewport {
+            origin_x: 0.0,
+            origin_y: 0.0,
+            offset_x: 0,
+            offset_y: 0,
+            base_scale: 1.0,
+            level: 0,
+            rotation: 0.0,
+            aspect: 1.0,
+        };
+        viewport.rotate(std::f64::consts::FRAC_PI_2);
+        let (x, y) = sample(&viewport, 2, 2, 2, 1);
+        assert!(x.abs() < 1e-12);
+        assert!((y - 1.0).abs() < 1e-12);
+        viewport.pan(1, 0);
+        let (x, y) = viewport.center();
+        assert!(x.abs() < 1e-12);
+        assert!((y - 1.0).abs() < 1e-12);
+    }
+}
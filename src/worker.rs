@@ -0,0 +1,121 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+    mpsc::{Receiver, Sender, channel},
+};
+
+/// Gets set once the job it was handed out with has been replaced by a newer one
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs jobs one at a time on a background thread.
+/// Starting a job cancels the one in progress,
+/// and only the outputs of the newest job are ever received
+#[derive(Debug)]
+pub struct Worker<J, O> {
+    jobs: Sender<(u64, J, CancelToken)>,
+    outputs: Receiver<(u64, O)>,
+    generation: u64,
+    cancel: CancelToken,
+}
+
+impl<J: Send + 'static, O: Send + 'static> Worker<J, O> {
+    /// `work` gets each job, the token that says when to give up on it,
+    /// and a function to send any number of outputs back through
+    pub fn spawn(work: impl Fn(J, &CancelToken, &dyn Fn(O)) + Send + 'static) -> Self {
+        let (jobs, job_receiver) = channel::<(u64, J, CancelToken)>();
+        let (output_sender, outputs) = channel();
+        std::thread::spawn(move || {
+            while let Ok(mut next) = job_receiver.recv() {
+                // Anything still queued behind it is newer, so skip straight to the newest
+                while let Ok(newer) = job_receiver.try_recv() {
+                    next = newer;
+                }
+                let (generation, job, cancel) = next;
+                work(job, &cancel, &|output| {
+                    let _ = output_sender.send((generation, output));
+                });
+            }
+        });
+        Self {
+            jobs,
+            outputs,
+            generation: 0,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    pub fn start(&mut self, job: J) {
+        self.cancel.cancel();
+        self.cancel = CancelToken::default();
+        self.generation += 1;
+        self.jobs
+            .send((self.generation, job, self.cancel.clone()))
+            .unwrap();
+    }
+
+    /// The next output of the newest job, if there is one yet
+    pub fn try_recv(&self) -> Option<O> {
+        loop {
+            let (generation, output) = self.outputs.try_recv().ok()?;
+            if generation == self.generation {
+                return Some(output);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Worker;
+    use std::time::{Duration, Instant};
+
+    fn recv_within<J: Send + 'static, O: Send + 'static>(worker: &Worker<J, O>) -> Option<O> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(output) = worker.try_recv() {
+                return Some(output);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    #[test]
+    fn outputs_come_back() {
+        let mut worker = Worker::spawn(|job: u32, _, send| {
+            send(job);
+            send(job * 2);
+        });
+        worker.start(21);
+        assert_eq!(recv_within(&worker), Some(21));
+        assert_eq!(recv_within(&worker), Some(42));
+    }
+
+    #[test]
+    fn starting_a_job_cancels_the_previous_one() {
+        let mut worker = Worker::spawn(|job: Option<u32>, cancel, send| match job {
+            // Only finishes once it's been cancelled
+            None => {
+                while !cancel.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                send(0);
+            }
+            Some(n) => send(n),
+        });
+        worker.start(None);
+        worker.start(Some(7));
+        assert_eq!(recv_within(&worker), Some(7));
+        assert_eq!(worker.try_recv(), None);
+    }
+}